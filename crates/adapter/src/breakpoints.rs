@@ -0,0 +1,472 @@
+use std::collections::{BTreeMap, HashMap};
+
+use dap_ty::{
+    Breakpoint, BreakpointEventBody, DataBreakpoint, FunctionBreakpoint, InstructionBreakpoint,
+    SetBreakpointsArguments, SetBreakpointsResponseBody, SetDataBreakpointsArguments,
+    SetDataBreakpointsResponseBody, SetFunctionBreakpointsArguments,
+    SetFunctionBreakpointsResponseBody, SetInstructionBreakpointsArguments,
+    SetInstructionBreakpointsResponseBody, Source, SourceBreakpoint,
+};
+
+use crate::hit_condition::{check_hit_condition, Conditional};
+use crate::log_message::{parse_log_message, LogMessage};
+
+/// `reason` field of [`BreakpointEventBody`]
+pub mod reason {
+    pub const CHANGED: &str = "changed";
+    pub const NEW: &str = "new";
+    pub const REMOVED: &str = "removed";
+}
+
+/// key used to group source breakpoints, `path` if present, otherwise `sourceReference`
+pub fn source_key(source: &Source) -> Option<String> {
+    match (&source.path, source.source_reference) {
+        (Some(path), _) => Some(path.clone()),
+        (None, Some(reference)) if reference > 0.0 => Some(format!("#{}", reference)),
+        _ => None,
+    }
+}
+
+/// a breakpoint requested by client together with its reported state
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry<T> {
+    /// breakpoint as requested by client
+    pub spec: T,
+    /// breakpoint as reported to client, `id` is always set
+    pub breakpoint: Breakpoint,
+}
+
+impl<T> Entry<T> {
+    pub fn id(&self) -> i64 {
+        self.breakpoint.id.unwrap_or_default()
+    }
+
+    pub fn verified(&self) -> bool {
+        self.breakpoint.verified
+    }
+}
+
+/// owns every kind of breakpoint and implements "replace all" semantics of
+/// `setBreakpoints`, `setFunctionBreakpoints`, `setDataBreakpoints` and
+/// `setInstructionBreakpoints`
+///
+/// every `set_*` method takes a `verify` callback, it receives a prefilled unverified
+/// [`Breakpoint`] and may set `verified`, adjust `line` or fill `message`.
+/// breakpoints with an invalid `hitCondition` or `logMessage` are reported unverified regardless.
+/// breakpoints resolved later are updated with [`resolve`](Self::resolve), which queues
+/// a `breakpoint` event, drain queued events with [`take_events`](Self::take_events).
+#[derive(Debug, Clone, Default)]
+pub struct BreakpointStore {
+    last_id: i64,
+    sources: BTreeMap<String, Vec<Entry<SourceBreakpoint>>>,
+    /// breakpoints added by adapter without source path or reference
+    unsourced: Vec<Entry<SourceBreakpoint>>,
+    /// parsed `logMessage` of logpoints by id
+    logpoints: HashMap<i64, LogMessage>,
+    functions: Vec<Entry<FunctionBreakpoint>>,
+    data: Vec<Entry<DataBreakpoint>>,
+    instructions: Vec<Entry<InstructionBreakpoint>>,
+    events: Vec<BreakpointEventBody>,
+}
+
+impl BreakpointStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn next_id(&mut self) -> i64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    /// handle `setBreakpoints`, replace all breakpoints of `args.source`
+    ///
+    /// a breakpoint at the same line and column as an existing one keeps its id,
+    /// every breakpoint in the response has a distinct id
+    pub fn set_breakpoints<F>(
+        &mut self,
+        args: &SetBreakpointsArguments,
+        mut verify: F,
+    ) -> SetBreakpointsResponseBody
+    where
+        F: FnMut(&Source, &SourceBreakpoint, &mut Breakpoint),
+    {
+        let specs = match (&args.breakpoints, &args.lines) {
+            (Some(bps), _) => bps.clone(),
+            // deprecated `lines`
+            (None, Some(lines)) => lines
+                .iter()
+                .map(|line| SourceBreakpoint {
+                    column: None,
+                    condition: None,
+                    hit_condition: None,
+                    line: *line,
+                    log_message: None,
+                })
+                .collect(),
+            (None, None) => vec![],
+        };
+        let key = match source_key(&args.source) {
+            Some(key) => key,
+            None => {
+                tracing::warn!("setBreakpoints without source path or reference");
+                let breakpoints = specs
+                    .iter()
+                    .map(|spec| Breakpoint {
+                        line: Some(spec.line),
+                        message: Some("source has neither path nor reference".to_string()),
+                        ..Default::default()
+                    })
+                    .collect();
+                return SetBreakpointsResponseBody {
+                    error: None,
+                    breakpoints,
+                };
+            }
+        };
+        let mut old = self.sources.remove(&key).unwrap_or_default();
+        let mut entries = Vec::with_capacity(specs.len());
+        for spec in specs {
+            // each old id is reused once, duplicates in one request get fresh ids
+            let id = match old
+                .iter()
+                .position(|e| e.spec.line == spec.line && e.spec.column == spec.column)
+            {
+                Some(pos) => old.remove(pos).id(),
+                None => self.next_id(),
+            };
+            let mut breakpoint = Breakpoint {
+                id: Some(id),
+                line: Some(spec.line),
+                column: spec.column,
+                source: Some(args.source.clone()),
+                ..Default::default()
+            };
+            verify(&args.source, &spec, &mut breakpoint);
+            check_hit_condition(&spec, &mut breakpoint);
+            match parse_log_message(&spec, &mut breakpoint) {
+                Some(msg) => self.logpoints.insert(id, msg),
+                None => self.logpoints.remove(&id),
+            };
+            breakpoint.id = Some(id);
+            entries.push(Entry { spec, breakpoint });
+        }
+        for entry in old {
+            self.logpoints.remove(&entry.id());
+        }
+        let breakpoints = entries.iter().map(|e| e.breakpoint.clone()).collect();
+        if !entries.is_empty() {
+            self.sources.insert(key, entries);
+        }
+        SetBreakpointsResponseBody {
+            error: None,
+            breakpoints,
+        }
+    }
+
+    fn replace<T: Clone + Conditional>(
+        last_id: &mut i64,
+        specs: &[T],
+        mut verify: impl FnMut(&T, &mut Breakpoint),
+    ) -> Vec<Entry<T>> {
+        specs
+            .iter()
+            .map(|spec| {
+                *last_id += 1;
+                let id = *last_id;
+                let mut breakpoint = Breakpoint {
+                    id: Some(id),
+                    ..Default::default()
+                };
+                verify(spec, &mut breakpoint);
+                check_hit_condition(spec, &mut breakpoint);
+                breakpoint.id = Some(id);
+                Entry {
+                    spec: spec.clone(),
+                    breakpoint,
+                }
+            })
+            .collect()
+    }
+
+    /// handle `setFunctionBreakpoints`, replace all function breakpoints
+    pub fn set_function_breakpoints<F>(
+        &mut self,
+        args: &SetFunctionBreakpointsArguments,
+        verify: F,
+    ) -> SetFunctionBreakpointsResponseBody
+    where
+        F: FnMut(&FunctionBreakpoint, &mut Breakpoint),
+    {
+        self.functions = Self::replace(&mut self.last_id, &args.breakpoints, verify);
+        SetFunctionBreakpointsResponseBody {
+            error: None,
+            breakpoints: self
+                .functions
+                .iter()
+                .map(|e| e.breakpoint.clone())
+                .collect(),
+        }
+    }
+
+    /// handle `setDataBreakpoints`, replace all data breakpoints
+    pub fn set_data_breakpoints<F>(
+        &mut self,
+        args: &SetDataBreakpointsArguments,
+        verify: F,
+    ) -> SetDataBreakpointsResponseBody
+    where
+        F: FnMut(&DataBreakpoint, &mut Breakpoint),
+    {
+        self.data = Self::replace(&mut self.last_id, &args.breakpoints, verify);
+        SetDataBreakpointsResponseBody {
+            error: None,
+            breakpoints: self.data.iter().map(|e| e.breakpoint.clone()).collect(),
+        }
+    }
+
+    /// handle `setInstructionBreakpoints`, replace all instruction breakpoints
+    pub fn set_instruction_breakpoints<F>(
+        &mut self,
+        args: &SetInstructionBreakpointsArguments,
+        verify: F,
+    ) -> SetInstructionBreakpointsResponseBody
+    where
+        F: FnMut(&InstructionBreakpoint, &mut Breakpoint),
+    {
+        self.instructions = Self::replace(&mut self.last_id, &args.breakpoints, verify);
+        SetInstructionBreakpointsResponseBody {
+            error: None,
+            breakpoints: self
+                .instructions
+                .iter()
+                .map(|e| e.breakpoint.clone())
+                .collect(),
+        }
+    }
+
+    /// source breakpoints of a source, see [`source_key`]
+    pub fn source_breakpoints(&self, key: &str) -> &[Entry<SourceBreakpoint>] {
+        self.sources.get(key).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    /// find source breakpoint at `line`, matching reported line first, then requested line
+    pub fn find_at(&self, key: &str, line: i64) -> Option<&Entry<SourceBreakpoint>> {
+        let entries = self.source_breakpoints(key);
+        entries
+            .iter()
+            .find(|e| e.breakpoint.line == Some(line))
+            .or_else(|| entries.iter().find(|e| e.spec.line == line))
+    }
+
+    /// breakpoints [`add`](Self::add)ed without source path or reference
+    pub fn unsourced_breakpoints(&self) -> &[Entry<SourceBreakpoint>] {
+        &self.unsourced
+    }
+
+    /// parsed log message of logpoint `id`, `None` if it is not a valid logpoint
+    pub fn log_message(&self, id: i64) -> Option<&LogMessage> {
+        self.logpoints.get(&id)
+    }
+
+    pub fn function_breakpoints(&self) -> &[Entry<FunctionBreakpoint>] {
+        &self.functions
+    }
+
+    pub fn data_breakpoints(&self) -> &[Entry<DataBreakpoint>] {
+        &self.data
+    }
+
+    pub fn instruction_breakpoints(&self) -> &[Entry<InstructionBreakpoint>] {
+        &self.instructions
+    }
+
+    /// reported state of breakpoint `id`, whatever its kind
+    pub fn get(&self, id: i64) -> Option<&Breakpoint> {
+        self.iter_breakpoints().find(|bp| bp.id == Some(id))
+    }
+
+    fn iter_breakpoints(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.sources
+            .values()
+            .flatten()
+            .chain(&self.unsourced)
+            .map(|e| &e.breakpoint)
+            .chain(self.functions.iter().map(|e| &e.breakpoint))
+            .chain(self.data.iter().map(|e| &e.breakpoint))
+            .chain(self.instructions.iter().map(|e| &e.breakpoint))
+    }
+
+    fn breakpoint_mut(&mut self, id: i64) -> Option<&mut Breakpoint> {
+        self.sources
+            .values_mut()
+            .flatten()
+            .chain(&mut self.unsourced)
+            .map(|e| &mut e.breakpoint)
+            .chain(self.functions.iter_mut().map(|e| &mut e.breakpoint))
+            .chain(self.data.iter_mut().map(|e| &mut e.breakpoint))
+            .chain(self.instructions.iter_mut().map(|e| &mut e.breakpoint))
+            .find(|bp| bp.id == Some(id))
+    }
+
+    /// update breakpoint `id` after adapter resolved it, e.g. when a module is loaded,
+    /// queue a `changed` event if anything changed
+    ///
+    /// return false if breakpoint does not exist
+    pub fn resolve(&mut self, id: i64, f: impl FnOnce(&mut Breakpoint)) -> bool {
+        let changed = match self.breakpoint_mut(id) {
+            Some(bp) => {
+                let before = bp.clone();
+                f(bp);
+                bp.id = Some(id);
+                (*bp != before).then(|| bp.clone())
+            }
+            None => return false,
+        };
+        if let Some(breakpoint) = changed {
+            self.push_event(reason::CHANGED, breakpoint);
+        }
+        true
+    }
+
+    /// add a breakpoint created by adapter itself, e.g. a `debugger` statement,
+    /// queue a `new` event and return its id
+    ///
+    /// a breakpoint without source path or reference is kept apart, see
+    /// [`unsourced_breakpoints`](Self::unsourced_breakpoints)
+    pub fn add(&mut self, spec: SourceBreakpoint, mut breakpoint: Breakpoint) -> i64 {
+        let id = self.next_id();
+        breakpoint.id = Some(id);
+        if let Some(msg) = parse_log_message(&spec, &mut breakpoint) {
+            self.logpoints.insert(id, msg);
+        }
+        let entry = Entry {
+            spec,
+            breakpoint: breakpoint.clone(),
+        };
+        match breakpoint.source.as_ref().and_then(source_key) {
+            Some(key) => self.sources.entry(key).or_default().push(entry),
+            None => self.unsourced.push(entry),
+        }
+        self.push_event(reason::NEW, breakpoint);
+        id
+    }
+
+    /// remove breakpoint `id` and queue a `removed` event
+    ///
+    /// return false if breakpoint does not exist
+    pub fn remove(&mut self, id: i64) -> bool {
+        let mut removed = None;
+        for entries in self.sources.values_mut() {
+            if let Some(pos) = entries.iter().position(|e| e.id() == id) {
+                removed = Some(entries.remove(pos).breakpoint);
+            }
+        }
+        self.sources.retain(|_, entries| !entries.is_empty());
+        self.logpoints.remove(&id);
+        fn take<T>(entries: &mut Vec<Entry<T>>, id: i64) -> Option<Breakpoint> {
+            let pos = entries.iter().position(|e| e.id() == id)?;
+            Some(entries.remove(pos).breakpoint)
+        }
+        let removed = removed
+            .or_else(|| take(&mut self.unsourced, id))
+            .or_else(|| take(&mut self.functions, id))
+            .or_else(|| take(&mut self.data, id))
+            .or_else(|| take(&mut self.instructions, id));
+        match removed {
+            Some(breakpoint) => {
+                self.push_event(reason::REMOVED, breakpoint);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn push_event(&mut self, reason: &str, breakpoint: Breakpoint) {
+        self.events.push(BreakpointEventBody {
+            error: None,
+            breakpoint,
+            reason: reason.to_string(),
+        });
+    }
+
+    /// drain queued `breakpoint` event bodies
+    pub fn take_events(&mut self) -> Vec<BreakpointEventBody> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(lines: &[i64]) -> SetBreakpointsArguments {
+        SetBreakpointsArguments {
+            breakpoints: Some(
+                lines
+                    .iter()
+                    .map(|line| SourceBreakpoint {
+                        column: None,
+                        condition: None,
+                        hit_condition: None,
+                        line: *line,
+                        log_message: None,
+                    })
+                    .collect(),
+            ),
+            lines: None,
+            source: Source {
+                path: Some("/src/main.rs".to_string()),
+                ..Default::default()
+            },
+            source_modified: None,
+        }
+    }
+
+    fn ids(body: &SetBreakpointsResponseBody) -> Vec<i64> {
+        body.breakpoints.iter().map(|bp| bp.id.unwrap()).collect()
+    }
+
+    #[test]
+    fn same_location_keeps_id() {
+        let mut store = BreakpointStore::new();
+        let first = ids(&store.set_breakpoints(&args(&[1, 2]), |_, _, _| {}));
+        let second = ids(&store.set_breakpoints(&args(&[2, 3]), |_, _, _| {}));
+        assert_eq!(second[0], first[1]);
+        assert!(!first.contains(&second[1]));
+    }
+
+    #[test]
+    fn duplicates_get_distinct_ids() {
+        let mut store = BreakpointStore::new();
+        let first = ids(&store.set_breakpoints(&args(&[5, 5]), |_, _, _| {}));
+        assert_ne!(first[0], first[1]);
+        let second = ids(&store.set_breakpoints(&args(&[5, 5, 5]), |_, _, _| {}));
+        assert_eq!(&second[..2], &first[..]);
+        assert!(!first.contains(&second[2]));
+        assert!(store.resolve(second[2], |bp| bp.verified = true));
+        assert_eq!(store.take_events().len(), 1);
+    }
+
+    #[test]
+    fn added_without_source_is_kept_apart() {
+        let mut store = BreakpointStore::new();
+        let spec = args(&[7]).breakpoints.unwrap().remove(0);
+        let id = store.add(
+            spec,
+            Breakpoint {
+                verified: true,
+                line: Some(7),
+                ..Default::default()
+            },
+        );
+        assert_eq!(store.unsourced_breakpoints().len(), 1);
+        assert!(store.source_breakpoints("").is_empty());
+        assert!(store.get(id).is_some());
+        assert!(store.resolve(id, |bp| bp.verified = false));
+        assert!(store.remove(id));
+        assert!(store.unsourced_breakpoints().is_empty());
+        let reasons: Vec<_> = store.take_events().into_iter().map(|e| e.reason).collect();
+        assert_eq!(reasons, [reason::NEW, reason::CHANGED, reason::REMOVED]);
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use dap_ty::{
+    ContinueArguments, ContinuedEventBody, Event, FromEvent, FromReq, GotoArguments, NextArguments,
+    OneOf3, Request, Response, RestartFrameArguments, ReverseContinueArguments, StepBackArguments,
+    StepInArguments, StepOutArguments,
+};
+
+/// commands whose successful response means debuggee resumed
+pub const RESUME_COMMANDS: &[&str] = &[
+    ContinueArguments::COMMAND,
+    NextArguments::COMMAND,
+    StepInArguments::COMMAND,
+    StepOutArguments::COMMAND,
+    StepBackArguments::COMMAND,
+    ReverseContinueArguments::COMMAND,
+    GotoArguments::COMMAND,
+    RestartFrameArguments::COMMAND,
+];
+
+/// return true if an outgoing message means debuggee resumed, i.e. a successful
+/// response of a [resume command](RESUME_COMMANDS) or a `continued` event
+pub fn is_resume(msg: &OneOf3<Request, Response, Event>) -> bool {
+    match msg {
+        OneOf3::Among(resp) => resp.success && RESUME_COMMANDS.contains(&resp.command.as_str()),
+        OneOf3::Other(event) => ContinuedEventBody::can_cast(event),
+        OneOf3::This(_) => false,
+    }
+}
+
+/// how many times debuggee resumed, cheap to clone, clones share the count
+///
+/// tables created with [`HandleTable::tracking`] reset themselves once the count changes,
+/// so whoever sends messages to client, e.g. `Ctx` of dap-io, only needs to
+/// [`observe`](Self::observe) them and every bound table is invalidated on resume.
+#[derive(Debug, Clone, Default)]
+pub struct Resumes(Arc<AtomicU64>);
+
+impl Resumes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// record that debuggee resumed
+    pub fn bump(&self) {
+        self.0.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// inspect an outgoing message and bump count if it resumes debuggee,
+    /// return true if count was bumped
+    pub fn observe(&self, msg: &OneOf3<Request, Response, Event>) -> bool {
+        let resumed = is_resume(msg);
+        if resumed {
+            self.bump();
+        }
+        resumed
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleError {
+    /// handle was valid before debuggee resumed
+    Stale(i64),
+    /// handle was never handed out
+    Unknown(i64),
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandleError::Stale(h) => write!(f, "reference {} is no longer valid", h),
+            HandleError::Unknown(h) => write!(f, "unknown reference {}", h),
+        }
+    }
+}
+
+impl std::error::Error for HandleError {}
+
+/// hands out `variablesReference`, `frameId` or `sourceReference` values
+///
+/// handles are never reused, so a handle from before a [`reset`](Self::reset) is reported
+/// as [`HandleError::Stale`] instead of silently pointing to new data. bind a table to
+/// [`Resumes`] with [`tracking`](Self::tracking) to have it reset on resume automatically.
+/// handles start from 1 since 0 means "no reference" in the protocol.
+#[derive(Debug, Clone)]
+pub struct HandleTable<T> {
+    items: HashMap<i64, T>,
+    next: i64,
+    /// first handle of current generation
+    valid_from: i64,
+    /// reset when debuggee resumes
+    stop_scoped: bool,
+    /// bound counter and its count at last reset
+    resumes: Option<(Resumes, u64)>,
+}
+
+impl<T> Default for HandleTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> HandleTable<T> {
+    /// table for `variablesReference` and `frameId`, invalidated when debuggee resumes
+    pub fn new() -> Self {
+        Self {
+            items: HashMap::new(),
+            next: 1,
+            valid_from: 1,
+            stop_scoped: true,
+            resumes: None,
+        }
+    }
+
+    /// stop scoped table which resets itself whenever `resumes` changes
+    pub fn tracking(resumes: Resumes) -> Self {
+        let count = resumes.count();
+        Self {
+            resumes: Some((resumes, count)),
+            ..Self::new()
+        }
+    }
+
+    /// table for `sourceReference`, valid for whole session, [`observe`](Self::observe)
+    /// never resets it
+    pub fn session_scoped() -> Self {
+        Self {
+            stop_scoped: false,
+            ..Self::new()
+        }
+    }
+
+    /// true if bound counter changed since last reset
+    fn outdated(&self) -> bool {
+        matches!(&self.resumes, Some((resumes, seen)) if resumes.count() != *seen)
+    }
+
+    /// reset if bound counter changed
+    fn sync(&mut self) {
+        if self.outdated() {
+            self.reset();
+        }
+    }
+
+    /// store `value` and return its handle
+    pub fn insert(&mut self, value: T) -> i64 {
+        self.sync();
+        let handle = self.next;
+        self.next += 1;
+        self.items.insert(handle, value);
+        handle
+    }
+
+    fn error(&self, handle: i64) -> HandleError {
+        let valid_from = if self.outdated() {
+            self.next
+        } else {
+            self.valid_from
+        };
+        if handle > 0 && handle < valid_from {
+            HandleError::Stale(handle)
+        } else {
+            HandleError::Unknown(handle)
+        }
+    }
+
+    pub fn get(&self, handle: i64) -> Result<&T, HandleError> {
+        if self.outdated() {
+            return Err(self.error(handle));
+        }
+        self.items.get(&handle).ok_or_else(|| self.error(handle))
+    }
+
+    pub fn get_mut(&mut self, handle: i64) -> Result<&mut T, HandleError> {
+        self.sync();
+        let err = self.error(handle);
+        self.items.get_mut(&handle).ok_or(err)
+    }
+
+    /// find handle of an already stored value
+    pub fn find(&self, f: impl Fn(&T) -> bool) -> Option<i64> {
+        if self.outdated() {
+            return None;
+        }
+        self.items
+            .iter()
+            .find(|(_, value)| f(value))
+            .map(|(handle, _)| *handle)
+    }
+
+    pub fn len(&self) -> usize {
+        if self.outdated() {
+            0
+        } else {
+            self.items.len()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// invalidate every handle handed out so far
+    pub fn reset(&mut self) {
+        self.items.clear();
+        self.valid_from = self.next;
+        if let Some((resumes, seen)) = &mut self.resumes {
+            *seen = resumes.count();
+        }
+    }
+
+    /// inspect an outgoing message and reset table if it resumes debuggee,
+    /// return true if table was reset
+    ///
+    /// not needed for tables created with [`tracking`](Self::tracking)
+    pub fn observe(&mut self, msg: &OneOf3<Request, Response, Event>) -> bool {
+        if self.stop_scoped && is_resume(msg) {
+            self.reset();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn message(value: serde_json::Value) -> OneOf3<Request, Response, Event> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn continue_response(success: bool) -> OneOf3<Request, Response, Event> {
+        message(json!({
+            "seq": 1,
+            "type": "response",
+            "request_seq": 1,
+            "command": "continue",
+            "success": success,
+        }))
+    }
+
+    #[test]
+    fn observe_resets_on_resume() {
+        let mut table = HandleTable::new();
+        let handle = table.insert("a");
+        assert!(!table.observe(&continue_response(false)));
+        assert_eq!(table.get(handle), Ok(&"a"));
+        assert!(table.observe(&continue_response(true)));
+        assert_eq!(table.get(handle), Err(HandleError::Stale(handle)));
+        assert_eq!(table.get(handle + 1), Err(HandleError::Unknown(handle + 1)));
+        assert!(table.insert("b") > handle);
+    }
+
+    #[test]
+    fn tracking_table_resets_on_bump() {
+        let resumes = Resumes::new();
+        let mut table = HandleTable::tracking(resumes.clone());
+        let handle = table.insert(1);
+        assert!(resumes.observe(&message(
+            json!({"seq": 2, "type": "event", "event": "continued", "body": {"threadId": 1}})
+        )));
+        assert_eq!(table.get(handle), Err(HandleError::Stale(handle)));
+        assert!(table.is_empty());
+        assert_eq!(table.find(|v| *v == 1), None);
+
+        let handle = table.insert(2);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.get(handle), Ok(&2));
+    }
+
+    #[test]
+    fn session_scoped_survives_resume() {
+        let mut table = HandleTable::session_scoped();
+        let handle = table.insert("source");
+        assert!(!table.observe(&continue_response(true)));
+        assert_eq!(table.get(handle), Ok(&"source"));
+    }
+}
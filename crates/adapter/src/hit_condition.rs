@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use dap_ty::{
+    Breakpoint, DataBreakpoint, FunctionBreakpoint, InstructionBreakpoint, SourceBreakpoint,
+};
+
+use crate::breakpoints::Entry;
+
+/// breakpoint kinds carrying `condition` and `hitCondition`
+pub trait Conditional {
+    fn condition(&self) -> Option<&str>;
+    fn hit_condition(&self) -> Option<&str>;
+}
+
+macro_rules! impl_conditional {
+    ($($ty:ty),*) => {
+        $(
+            impl Conditional for $ty {
+                fn condition(&self) -> Option<&str> {
+                    self.condition.as_deref()
+                }
+
+                fn hit_condition(&self) -> Option<&str> {
+                    self.hit_condition.as_deref()
+                }
+            }
+        )*
+    };
+}
+
+impl_conditional!(
+    SourceBreakpoint,
+    FunctionBreakpoint,
+    DataBreakpoint,
+    InstructionBreakpoint
+);
+
+/// parsed `hitCondition`
+///
+/// supported syntax is an optional operator followed by a number, `5` is same as `== 5`,
+/// `% 3` breaks on every third hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HitCondition {
+    Eq(u64),
+    Gt(u64),
+    Ge(u64),
+    Lt(u64),
+    Le(u64),
+    Multiple(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HitConditionError(pub String);
+
+impl fmt::Display for HitConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid hit condition {:?}", self.0)
+    }
+}
+
+impl std::error::Error for HitConditionError {}
+
+type Ctor = fn(u64) -> HitCondition;
+
+impl FromStr for HitCondition {
+    type Err = HitConditionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || HitConditionError(s.to_string());
+        let s = s.trim();
+        // longer operators first
+        let ops: [(&str, Ctor); 7] = [
+            (">=", HitCondition::Ge),
+            ("<=", HitCondition::Le),
+            ("==", HitCondition::Eq),
+            (">", HitCondition::Gt),
+            ("<", HitCondition::Lt),
+            ("=", HitCondition::Eq),
+            ("%", HitCondition::Multiple),
+        ];
+        let (ctor, num) = ops
+            .iter()
+            .find_map(|(op, ctor)| s.strip_prefix(op).map(|rest| (*ctor, rest)))
+            .unwrap_or((HitCondition::Eq, s));
+        let n: u64 = num.trim().parse().map_err(|_| err())?;
+        match ctor(n) {
+            HitCondition::Multiple(0) => Err(err()),
+            cond => Ok(cond),
+        }
+    }
+}
+
+impl HitCondition {
+    /// whether breakpoint stops on its `hits`-th hit, counting from 1
+    pub fn matches(&self, hits: u64) -> bool {
+        match *self {
+            HitCondition::Eq(n) => hits == n,
+            HitCondition::Gt(n) => hits > n,
+            HitCondition::Ge(n) => hits >= n,
+            HitCondition::Lt(n) => hits < n,
+            HitCondition::Le(n) => hits <= n,
+            // `% 0` never matches
+            HitCondition::Multiple(n) => hits.checked_rem(n) == Some(0),
+        }
+    }
+}
+
+/// mark `breakpoint` unverified if `spec` has an unparsable hit condition
+///
+/// return false if hit condition is invalid
+pub fn check_hit_condition<T: Conditional>(spec: &T, breakpoint: &mut Breakpoint) -> bool {
+    match spec
+        .hit_condition()
+        .filter(|c| !c.trim().is_empty())
+        .map(HitCondition::from_str)
+    {
+        Some(Err(e)) => {
+            breakpoint.verified = false;
+            breakpoint.message = Some(e.to_string());
+            false
+        }
+        _ => true,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Hits {
+    count: u64,
+    /// `condition` and `hitCondition` the counter belongs to
+    condition: Option<String>,
+    hit_condition: Option<String>,
+}
+
+/// evaluates `condition` and `hitCondition` and keeps per-breakpoint hit counters
+///
+/// a counter restarts from zero when `condition` or `hitCondition` of its breakpoint changes
+#[derive(Debug, Clone, Default)]
+pub struct BreakpointConditions {
+    hits: HashMap<i64, Hits>,
+}
+
+impl BreakpointConditions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// decide whether thread stops at breakpoint `entry`
+    ///
+    /// `condition` is evaluated with `eval` first, a false condition does not count as hit.
+    /// evaluation errors are returned as is, so caller can stop and show them.
+    pub fn should_stop<T, F>(&mut self, entry: &Entry<T>, eval: F) -> Result<bool, String>
+    where
+        T: Conditional,
+        F: FnOnce(&str) -> Result<bool, String>,
+    {
+        if !entry.verified() {
+            return Ok(false);
+        }
+        let hits = self.hits.entry(entry.id()).or_default();
+        if hits.condition.as_deref() != entry.spec.condition()
+            || hits.hit_condition.as_deref() != entry.spec.hit_condition()
+        {
+            *hits = Hits {
+                count: 0,
+                condition: entry.spec.condition().map(String::from),
+                hit_condition: entry.spec.hit_condition().map(String::from),
+            };
+        }
+        if let Some(condition) = entry.spec.condition().filter(|c| !c.trim().is_empty()) {
+            if !eval(condition)? {
+                return Ok(false);
+            }
+        }
+        hits.count += 1;
+        match entry
+            .spec
+            .hit_condition()
+            .filter(|c| !c.trim().is_empty())
+            .map(HitCondition::from_str)
+        {
+            Some(Ok(cond)) => Ok(cond.matches(hits.count)),
+            Some(Err(e)) => Err(e.to_string()),
+            None => Ok(true),
+        }
+    }
+
+    /// hit count of breakpoint `id`
+    pub fn hits(&self, id: i64) -> u64 {
+        self.hits.get(&id).map(|h| h.count).unwrap_or_default()
+    }
+
+    /// reset hit counters, e.g. on `restart`
+    pub fn reset(&mut self) {
+        self.hits.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<HitCondition, HitConditionError> {
+        s.parse()
+    }
+
+    #[test]
+    fn parse_operators() {
+        assert_eq!(parse("5"), Ok(HitCondition::Eq(5)));
+        assert_eq!(parse("== 5"), Ok(HitCondition::Eq(5)));
+        assert_eq!(parse("=5"), Ok(HitCondition::Eq(5)));
+        assert_eq!(parse(">3"), Ok(HitCondition::Gt(3)));
+        assert_eq!(parse(" >= 3 "), Ok(HitCondition::Ge(3)));
+        assert_eq!(parse("<3"), Ok(HitCondition::Lt(3)));
+        assert_eq!(parse("<=3"), Ok(HitCondition::Le(3)));
+        assert_eq!(parse("% 2"), Ok(HitCondition::Multiple(2)));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for s in ["", "abc", ">", "% 0", "-1", "> -1", "=> 3", "1.5", "5 5"] {
+            assert_eq!(parse(s), Err(HitConditionError(s.to_string())), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn matches() {
+        assert!(HitCondition::Eq(2).matches(2));
+        assert!(!HitCondition::Eq(2).matches(3));
+        assert!(HitCondition::Gt(2).matches(3));
+        assert!(!HitCondition::Gt(2).matches(2));
+        assert!(HitCondition::Ge(2).matches(2));
+        assert!(HitCondition::Lt(2).matches(1));
+        assert!(HitCondition::Le(2).matches(2));
+        let hits: Vec<u64> = (1..=7)
+            .filter(|h| HitCondition::Multiple(3).matches(*h))
+            .collect();
+        assert_eq!(hits, [3, 6]);
+        assert!(!HitCondition::Multiple(0).matches(0));
+    }
+
+    fn entry(condition: Option<&str>, hit_condition: Option<&str>) -> Entry<SourceBreakpoint> {
+        let spec = SourceBreakpoint {
+            column: None,
+            condition: condition.map(String::from),
+            hit_condition: hit_condition.map(String::from),
+            line: 1,
+            log_message: None,
+        };
+        let mut breakpoint = Breakpoint {
+            id: Some(1),
+            verified: true,
+            ..Default::default()
+        };
+        check_hit_condition(&spec, &mut breakpoint);
+        Entry { spec, breakpoint }
+    }
+
+    #[test]
+    fn invalid_hit_condition_unverifies() {
+        let entry = entry(None, Some("often"));
+        assert!(!entry.verified());
+        assert!(entry.breakpoint.message.is_some());
+        let mut conditions = BreakpointConditions::new();
+        assert_eq!(conditions.should_stop(&entry, |_| Ok(true)), Ok(false));
+    }
+
+    #[test]
+    fn false_condition_is_not_a_hit() {
+        let entry = entry(Some("x > 1"), Some(">= 2"));
+        let mut conditions = BreakpointConditions::new();
+        assert_eq!(conditions.should_stop(&entry, |_| Ok(false)), Ok(false));
+        assert_eq!(conditions.hits(1), 0);
+        assert_eq!(conditions.should_stop(&entry, |_| Ok(true)), Ok(false));
+        assert_eq!(conditions.should_stop(&entry, |_| Ok(true)), Ok(true));
+        assert_eq!(conditions.hits(1), 2);
+        assert_eq!(
+            conditions.should_stop(&entry, |_| Err("boom".to_string())),
+            Err("boom".to_string())
+        );
+        conditions.reset();
+        assert_eq!(conditions.hits(1), 0);
+    }
+
+    #[test]
+    fn changed_condition_restarts_counter() {
+        let mut conditions = BreakpointConditions::new();
+        let entry_a = entry(None, Some("3"));
+        for _ in 0..2 {
+            conditions.should_stop(&entry_a, |_| Ok(true)).unwrap();
+        }
+        assert_eq!(conditions.hits(1), 2);
+
+        // same id, new hitCondition
+        let entry_b = entry(None, Some("2"));
+        assert_eq!(conditions.should_stop(&entry_b, |_| Ok(true)), Ok(false));
+        assert_eq!(conditions.hits(1), 1);
+        assert_eq!(conditions.should_stop(&entry_b, |_| Ok(true)), Ok(true));
+
+        // new condition restarts counter even if it is false
+        let entry_c = entry(Some("x"), Some("2"));
+        assert_eq!(conditions.should_stop(&entry_c, |_| Ok(false)), Ok(false));
+        assert_eq!(conditions.hits(1), 0);
+    }
+}
@@ -0,0 +1,14 @@
+//! building blocks for debug adapters, independent of transport
+mod breakpoints;
+mod handles;
+mod hit_condition;
+mod log_message;
+mod stepping;
+mod variables;
+
+pub use breakpoints::*;
+pub use handles::*;
+pub use hit_condition::*;
+pub use log_message::*;
+pub use stepping::*;
+pub use variables::*;
@@ -0,0 +1,229 @@
+use std::fmt;
+use std::str::FromStr;
+
+use dap_ty::{Breakpoint, OutputEventBody, Source, SourceBreakpoint};
+
+/// `category` of logpoint output
+pub const CONSOLE: &str = "console";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Expr(String),
+}
+
+/// parsed `logMessage` of a logpoint
+///
+/// text inside `{}` is an expression, literal braces are written as `{{` and `}}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogMessage {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogMessageError {
+    /// byte offset of the offending brace
+    pub pos: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for LogMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid log message at {}: {}", self.pos, self.reason)
+    }
+}
+
+impl std::error::Error for LogMessageError {}
+
+impl FromStr for LogMessage {
+    type Err = LogMessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = s.char_indices().peekable();
+        while let Some((pos, c)) = chars.next() {
+            match c {
+                '{' if matches!(chars.peek(), Some((_, '{'))) => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if matches!(chars.peek(), Some((_, '}'))) => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => {
+                    return Err(LogMessageError {
+                        pos,
+                        reason: "unmatched '}'",
+                    })
+                }
+                '{' => {
+                    let mut expr = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '}')) => break,
+                            Some((_, '{')) | None => {
+                                return Err(LogMessageError {
+                                    pos,
+                                    reason: "unclosed '{'",
+                                })
+                            }
+                            Some((_, c)) => expr.push(c),
+                        }
+                    }
+                    if expr.trim().is_empty() {
+                        return Err(LogMessageError {
+                            pos,
+                            reason: "empty expression",
+                        });
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Expr(expr.trim().to_string()));
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+}
+
+impl LogMessage {
+    /// expressions in order of appearance
+    pub fn expressions(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|p| match p {
+            Part::Expr(expr) => Some(expr.as_str()),
+            Part::Text(_) => None,
+        })
+    }
+
+    /// interpolate expressions, failed evaluation is rendered as `<error: ..>`
+    pub fn render<F>(&self, mut eval: F) -> String
+    where
+        F: FnMut(&str) -> Result<String, String>,
+    {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Expr(expr) => match eval(expr) {
+                    Ok(value) => out.push_str(&value),
+                    Err(e) => {
+                        out.push_str("<error: ");
+                        out.push_str(&e);
+                        out.push('>');
+                    }
+                },
+            }
+        }
+        out
+    }
+
+    /// render message as an `output` event body of [`CONSOLE`] category
+    pub fn output<F>(&self, eval: F, source: Option<Source>, line: Option<i64>) -> OutputEventBody
+    where
+        F: FnMut(&str) -> Result<String, String>,
+    {
+        let mut output = self.render(eval);
+        output.push('\n');
+        OutputEventBody {
+            category: Some(CONSOLE.to_string()),
+            output,
+            source,
+            line,
+            ..Default::default()
+        }
+    }
+}
+
+/// mark `breakpoint` unverified if `spec` has a malformed log message
+///
+/// return false if log message is invalid
+pub fn check_log_message(spec: &SourceBreakpoint, breakpoint: &mut Breakpoint) -> bool {
+    spec.log_message.is_none() || parse_log_message(spec, breakpoint).is_some()
+}
+
+/// parse log message of `spec`, mark `breakpoint` unverified if it is malformed
+pub fn parse_log_message(
+    spec: &SourceBreakpoint,
+    breakpoint: &mut Breakpoint,
+) -> Option<LogMessage> {
+    match spec.log_message.as_deref()?.parse() {
+        Ok(msg) => Some(msg),
+        Err(e) => {
+            breakpoint.verified = false;
+            breakpoint.message = Some(e.to_string());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<LogMessage, LogMessageError> {
+        s.parse()
+    }
+
+    fn echo(expr: &str) -> Result<String, String> {
+        Ok(format!("<{}>", expr))
+    }
+
+    #[test]
+    fn interpolate_expressions() {
+        let msg = parse("x = {x}, y = { a.b }").unwrap();
+        assert_eq!(msg.expressions().collect::<Vec<_>>(), ["x", "a.b"]);
+        assert_eq!(msg.render(echo), "x = <x>, y = <a.b>");
+    }
+
+    #[test]
+    fn escaped_braces() {
+        let msg = parse("{{literal}} {x}}}").unwrap();
+        assert_eq!(msg.expressions().collect::<Vec<_>>(), ["x"]);
+        assert_eq!(msg.render(echo), "{literal} <x>}");
+        assert_eq!(parse("{{}}").unwrap().render(echo), "{}");
+    }
+
+    #[test]
+    fn plain_text() {
+        let msg = parse("hello").unwrap();
+        assert_eq!(msg.expressions().count(), 0);
+        assert_eq!(msg.render(echo), "hello");
+        assert_eq!(parse("").unwrap().render(echo), "");
+    }
+
+    #[test]
+    fn unclosed_brace() {
+        let err = parse("a {x").unwrap_err();
+        assert_eq!(err.pos, 2);
+        assert_eq!(err.reason, "unclosed '{'");
+        assert_eq!(parse("{a {b}}").unwrap_err().reason, "unclosed '{'");
+    }
+
+    #[test]
+    fn unmatched_closing_brace() {
+        let err = parse("a } b").unwrap_err();
+        assert_eq!(err.pos, 2);
+        assert_eq!(err.reason, "unmatched '}'");
+    }
+
+    #[test]
+    fn empty_expression() {
+        assert_eq!(parse("{ }").unwrap_err().reason, "empty expression");
+    }
+
+    #[test]
+    fn failed_evaluation_is_rendered() {
+        let msg = parse("v={v}").unwrap();
+        let output = msg.output(|_| Err("not found".to_string()), None, Some(3));
+        assert_eq!(output.output, "v=<error: not found>\n");
+        assert_eq!(output.category.as_deref(), Some(CONSOLE));
+        assert_eq!(output.line, Some(3));
+    }
+}
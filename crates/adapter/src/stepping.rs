@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+
+use dap_ty::{
+    ContinueArguments, FromReq, NextArguments, OutputEventBody, StepInArguments, StepOutArguments,
+    StoppedEventBody,
+};
+
+use crate::breakpoints::BreakpointStore;
+use crate::hit_condition::BreakpointConditions;
+
+/// `reason` field of [`StoppedEventBody`]
+pub mod stop_reason {
+    pub const STEP: &str = "step";
+    pub const BREAKPOINT: &str = "breakpoint";
+    pub const EXCEPTION: &str = "exception";
+    pub const PAUSE: &str = "pause";
+    pub const ENTRY: &str = "entry";
+    pub const FUNCTION_BREAKPOINT: &str = "function breakpoint";
+}
+
+/// a position in interpreted code
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    /// source key, same as [`source_key`](crate::source_key) of the breakpoint source
+    pub source: String,
+    pub line: i64,
+}
+
+/// what an interpreter exposes to [`Stepper`]
+pub trait DebugHooks {
+    /// location of statement about to run
+    fn location(&self) -> Location;
+
+    /// call stack depth, outermost frame is 0
+    fn depth(&self) -> usize;
+
+    /// id of current thread, as reported in `threads` response
+    fn thread_id(&self) -> i64;
+
+    /// evaluate breakpoint `condition` in current frame
+    fn evaluate_condition(&self, condition: &str) -> Result<bool, String> {
+        Err(format!("conditions are not supported: {}", condition))
+    }
+
+    /// evaluate a logpoint expression in current frame
+    fn evaluate(&self, expression: &str) -> Result<String, String> {
+        Err(format!("evaluation is not supported: {}", expression))
+    }
+}
+
+/// how a stopped thread resumes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepAction {
+    Continue,
+    Next,
+    StepIn,
+    StepOut,
+}
+
+impl StepAction {
+    /// map `continue`, `next`, `stepIn` and `stepOut` commands
+    pub fn from_command(command: &str) -> Option<Self> {
+        match command {
+            ContinueArguments::COMMAND => Some(Self::Continue),
+            NextArguments::COMMAND => Some(Self::Next),
+            StepInArguments::COMMAND => Some(Self::StepIn),
+            StepOutArguments::COMMAND => Some(Self::StepOut),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mode {
+    Run,
+    Pause,
+    StepIn { from: Location, depth: usize },
+    Next { from: Location, depth: usize },
+    StepOut { depth: usize },
+}
+
+#[derive(Debug, Clone)]
+struct ThreadState {
+    mode: Mode,
+    /// statement thread already stopped before by [`Stepper::on_entry`], its breakpoints
+    /// are not checked when it runs right after
+    entry: Option<(Location, usize)>,
+}
+
+impl Default for ThreadState {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Run,
+            entry: None,
+        }
+    }
+}
+
+/// stepping engine shared by interpreters
+///
+/// call [`on_statement`](Self::on_statement) before every statement and
+/// [`on_call`](Self::on_call) on every function entry, send returned
+/// [`StoppedEventBody`] and block the thread until client resumes it with
+/// [`resume`](Self::resume). logpoints never stop, their output is queued,
+/// drain it with [`take_output`](Self::take_output).
+///
+/// breakpoints are checked on every `on_statement` call, so a breakpoint in a loop hits on
+/// every iteration. an interpreter running several statements per line should call it for
+/// the first statement of each line only.
+#[derive(Debug, Clone, Default)]
+pub struct Stepper {
+    threads: HashMap<i64, ThreadState>,
+    conditions: BreakpointConditions,
+    output: Vec<OutputEventBody>,
+}
+
+fn stopped(reason: &str, thread_id: i64, description: Option<String>) -> StoppedEventBody {
+    StoppedEventBody {
+        reason: reason.to_string(),
+        thread_id: Some(thread_id),
+        description,
+        ..Default::default()
+    }
+}
+
+impl Stepper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn thread(&mut self, thread_id: i64) -> &mut ThreadState {
+        self.threads.entry(thread_id).or_default()
+    }
+
+    /// resume a stopped thread
+    pub fn resume<H: DebugHooks>(&mut self, hooks: &H, action: StepAction) {
+        let from = hooks.location();
+        let depth = hooks.depth();
+        let state = self.thread(hooks.thread_id());
+        state.mode = match action {
+            StepAction::Continue => Mode::Run,
+            StepAction::Next => Mode::Next { from, depth },
+            StepAction::StepIn => Mode::StepIn { from, depth },
+            StepAction::StepOut => Mode::StepOut { depth },
+        };
+    }
+
+    /// handle `pause`, thread stops before its next statement
+    pub fn pause(&mut self, thread_id: i64) {
+        self.thread(thread_id).mode = Mode::Pause;
+    }
+
+    /// hit counters of breakpoints
+    pub fn conditions(&mut self) -> &mut BreakpointConditions {
+        &mut self.conditions
+    }
+
+    /// drain queued logpoint `output` event bodies
+    pub fn take_output(&mut self) -> Vec<OutputEventBody> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// forget a thread after it exits
+    pub fn remove_thread(&mut self, thread_id: i64) {
+        self.threads.remove(&thread_id);
+    }
+
+    /// stop before first statement, for `stopOnEntry`
+    pub fn on_entry<H: DebugHooks>(&mut self, hooks: &H) -> StoppedEventBody {
+        let state = self.thread(hooks.thread_id());
+        state.entry = Some((hooks.location(), hooks.depth()));
+        stopped(stop_reason::ENTRY, hooks.thread_id(), None)
+    }
+
+    /// report an exception, thread always stops
+    pub fn on_exception<H: DebugHooks>(
+        &mut self,
+        hooks: &H,
+        description: impl Into<String>,
+    ) -> StoppedEventBody {
+        let state = self.thread(hooks.thread_id());
+        state.mode = Mode::Run;
+        let mut body = stopped(
+            stop_reason::EXCEPTION,
+            hooks.thread_id(),
+            Some(description.into()),
+        );
+        body.text = body.description.clone();
+        body
+    }
+
+    /// check function breakpoints when entering function `name`
+    pub fn on_call<H: DebugHooks>(
+        &mut self,
+        hooks: &H,
+        breakpoints: &BreakpointStore,
+        name: &str,
+    ) -> Option<StoppedEventBody> {
+        let entry = breakpoints
+            .function_breakpoints()
+            .iter()
+            .find(|e| e.verified() && e.spec.name == name)?;
+        let description = match self
+            .conditions
+            .should_stop(entry, |c| hooks.evaluate_condition(c))
+        {
+            Ok(true) => format!("function breakpoint {}", name),
+            Ok(false) => return None,
+            Err(e) => format!("breakpoint condition error: {}", e),
+        };
+        let state = self.thread(hooks.thread_id());
+        state.mode = Mode::Run;
+        Some(stopped(
+            stop_reason::FUNCTION_BREAKPOINT,
+            hooks.thread_id(),
+            Some(description),
+        ))
+    }
+
+    /// decide whether thread stops before current statement
+    pub fn on_statement<H: DebugHooks>(
+        &mut self,
+        hooks: &H,
+        breakpoints: &BreakpointStore,
+    ) -> Option<StoppedEventBody> {
+        let thread_id = hooks.thread_id();
+        let location = hooks.location();
+        let depth = hooks.depth();
+        let state = self.threads.entry(thread_id).or_default();
+        let stopped_here = state.entry.take() == Some((location.clone(), depth));
+
+        let reason = match &state.mode {
+            Mode::Pause => Some(stop_reason::PAUSE),
+            Mode::StepIn { from, depth: d } if depth != *d || location != *from => {
+                Some(stop_reason::STEP)
+            }
+            Mode::Next { from, depth: d } if depth < *d || (depth == *d && location != *from) => {
+                Some(stop_reason::STEP)
+            }
+            Mode::StepOut { depth: d } if depth < *d => Some(stop_reason::STEP),
+            _ => None,
+        };
+        let hit = match breakpoints.find_at(&location.source, location.line) {
+            Some(entry) if !stopped_here => self
+                .conditions
+                .should_stop(entry, |c| hooks.evaluate_condition(c))
+                .map_err(|e| format!("breakpoint condition error: {}", e))
+                .map(|hit| match breakpoints.log_message(entry.id()) {
+                    Some(msg) if hit => {
+                        self.output.push(msg.output(
+                            |expr| hooks.evaluate(expr),
+                            entry.breakpoint.source.clone(),
+                            Some(location.line),
+                        ));
+                        false
+                    }
+                    // a malformed logpoint is unverified and never hits
+                    _ => hit,
+                }),
+            _ => Ok(false),
+        };
+        // a breakpoint on the line a step lands on is reported as breakpoint
+        let (reason, description) = match hit {
+            Ok(true) => (Some(stop_reason::BREAKPOINT), None),
+            Err(e) => (Some(stop_reason::BREAKPOINT), Some(e)),
+            Ok(false) => (reason, None),
+        };
+        let reason = reason?;
+        self.thread(thread_id).mode = Mode::Run;
+        Some(stopped(reason, thread_id, description))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dap_ty::{Breakpoint, SetBreakpointsArguments, Source, SourceBreakpoint};
+
+    use super::*;
+
+    const THREAD: i64 = 1;
+
+    struct FakeHooks {
+        line: i64,
+        depth: usize,
+    }
+
+    impl FakeHooks {
+        fn at(line: i64, depth: usize) -> Self {
+            Self { line, depth }
+        }
+    }
+
+    impl DebugHooks for FakeHooks {
+        fn location(&self) -> Location {
+            Location {
+                source: "/src/main.js".to_string(),
+                line: self.line,
+            }
+        }
+
+        fn depth(&self) -> usize {
+            self.depth
+        }
+
+        fn thread_id(&self) -> i64 {
+            THREAD
+        }
+
+        fn evaluate(&self, expression: &str) -> Result<String, String> {
+            Ok(format!("{}@{}", expression, self.line))
+        }
+    }
+
+    fn store(breakpoints: &[(i64, Option<&str>)]) -> BreakpointStore {
+        let mut store = BreakpointStore::new();
+        let args = SetBreakpointsArguments {
+            breakpoints: Some(
+                breakpoints
+                    .iter()
+                    .map(|(line, log_message)| SourceBreakpoint {
+                        column: None,
+                        condition: None,
+                        hit_condition: None,
+                        line: *line,
+                        log_message: log_message.map(String::from),
+                    })
+                    .collect(),
+            ),
+            lines: None,
+            source: Source {
+                path: Some("/src/main.js".to_string()),
+                ..Default::default()
+            },
+            source_modified: None,
+        };
+        store.set_breakpoints(&args, |_, _, bp: &mut Breakpoint| bp.verified = true);
+        store
+    }
+
+    fn reason(stopped: Option<StoppedEventBody>) -> Option<String> {
+        stopped.map(|body| body.reason)
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let mut stepper = Stepper::new();
+        let store = BreakpointStore::new();
+        stepper.resume(&FakeHooks::at(1, 0), StepAction::Next);
+        assert_eq!(stepper.on_statement(&FakeHooks::at(10, 1), &store), None);
+        assert_eq!(stepper.on_statement(&FakeHooks::at(11, 1), &store), None);
+        assert_eq!(
+            reason(stepper.on_statement(&FakeHooks::at(2, 0), &store)).as_deref(),
+            Some(stop_reason::STEP)
+        );
+        // thread runs freely after stopping
+        assert_eq!(stepper.on_statement(&FakeHooks::at(3, 0), &store), None);
+    }
+
+    #[test]
+    fn step_in_stops_in_callee() {
+        let mut stepper = Stepper::new();
+        let store = BreakpointStore::new();
+        stepper.resume(&FakeHooks::at(1, 0), StepAction::StepIn);
+        assert_eq!(
+            reason(stepper.on_statement(&FakeHooks::at(10, 1), &store)).as_deref(),
+            Some(stop_reason::STEP)
+        );
+    }
+
+    #[test]
+    fn step_out_stops_in_caller() {
+        let mut stepper = Stepper::new();
+        let store = BreakpointStore::new();
+        stepper.resume(&FakeHooks::at(10, 1), StepAction::StepOut);
+        assert_eq!(stepper.on_statement(&FakeHooks::at(11, 1), &store), None);
+        assert_eq!(stepper.on_statement(&FakeHooks::at(20, 2), &store), None);
+        assert_eq!(
+            reason(stepper.on_statement(&FakeHooks::at(2, 0), &store)).as_deref(),
+            Some(stop_reason::STEP)
+        );
+    }
+
+    #[test]
+    fn pause_stops_before_next_statement() {
+        let mut stepper = Stepper::new();
+        let store = BreakpointStore::new();
+        assert_eq!(stepper.on_statement(&FakeHooks::at(1, 0), &store), None);
+        stepper.pause(THREAD);
+        let stopped = stepper.on_statement(&FakeHooks::at(2, 0), &store).unwrap();
+        assert_eq!(stopped.reason, stop_reason::PAUSE);
+        assert_eq!(stopped.thread_id, Some(THREAD));
+        stepper.resume(&FakeHooks::at(2, 0), StepAction::Continue);
+        assert_eq!(stepper.on_statement(&FakeHooks::at(3, 0), &store), None);
+    }
+
+    #[test]
+    fn breakpoint_in_single_line_loop_hits_every_iteration() {
+        let mut stepper = Stepper::new();
+        let store = store(&[(5, None)]);
+        let hooks = FakeHooks::at(5, 0);
+        for _ in 0..3 {
+            assert_eq!(
+                reason(stepper.on_statement(&hooks, &store)).as_deref(),
+                Some(stop_reason::BREAKPOINT)
+            );
+            stepper.resume(&hooks, StepAction::Continue);
+        }
+        let id = store.source_breakpoints("/src/main.js")[0].id();
+        assert_eq!(stepper.conditions().hits(id), 3);
+    }
+
+    #[test]
+    fn entry_statement_does_not_hit_twice() {
+        let mut stepper = Stepper::new();
+        let store = store(&[(1, None)]);
+        let hooks = FakeHooks::at(1, 0);
+        assert_eq!(stepper.on_entry(&hooks).reason, stop_reason::ENTRY);
+        stepper.resume(&hooks, StepAction::Continue);
+        assert_eq!(stepper.on_statement(&hooks, &store), None);
+        // next iteration of the same line hits
+        assert!(stepper.on_statement(&hooks, &store).is_some());
+    }
+
+    #[test]
+    fn logpoints_log_without_stopping() {
+        let mut stepper = Stepper::new();
+        let store = store(&[(3, Some("x = {x}")), (4, Some("bad {"))]);
+        let bad = &store.source_breakpoints("/src/main.js")[1];
+        assert!(!bad.verified());
+        assert!(store.log_message(bad.id()).is_none());
+        for line in [3, 4, 3] {
+            assert_eq!(stepper.on_statement(&FakeHooks::at(line, 0), &store), None);
+        }
+        let output: Vec<_> = stepper
+            .take_output()
+            .into_iter()
+            .map(|o| o.output)
+            .collect();
+        assert_eq!(output, ["x = x@3\n", "x = x@3\n"]);
+    }
+}
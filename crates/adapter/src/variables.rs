@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use dap_ty::{
+    Event, OneOf3, Request, Response, ValueFormat, Variable, VariablesArguments,
+    VariablesResponseBody,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::handles::{HandleError, HandleTable, Resumes};
+
+/// `filter` field of [`VariablesArguments`]
+pub mod filter {
+    pub const INDEXED: &str = "indexed";
+    pub const NAMED: &str = "named";
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    value: Value,
+    evaluate_name: Option<String>,
+    /// references of expanded children, keyed by field name or index
+    children: HashMap<String, i64>,
+}
+
+/// exposes [`serde_json::Value`] as a `Variable` hierarchy
+///
+/// objects and arrays get a `variablesReference`, their children are expanded lazily
+/// when answering `variables` requests. a child keeps its reference when its parent is
+/// expanded again. object fields are reported as named variables,
+/// array items as indexed variables.
+#[derive(Debug, Clone, Default)]
+pub struct VariableTree {
+    handles: HandleTable<Node>,
+}
+
+/// json type name used as `type` hint
+pub fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(n) if n.is_f64() => "float",
+        Value::Number(_) => "int",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// short display text of `value`, integers are displayed in hex if `format.hex` is set
+pub fn format_value(value: &Value, format: Option<&ValueFormat>) -> String {
+    let hex = format.and_then(|f| f.hex).unwrap_or(false);
+    match value {
+        Value::Number(n) if hex && n.as_i64().is_some() => {
+            let n = n.as_i64().unwrap_or_default();
+            if n < 0 {
+                format!("-0x{:x}", n.unsigned_abs())
+            } else {
+                format!("0x{:x}", n)
+            }
+        }
+        Value::Number(n) if hex && n.as_u64().is_some() => {
+            format!("0x{:x}", n.as_u64().unwrap_or_default())
+        }
+        Value::Array(items) => format!("array({})", items.len()),
+        Value::Object(fields) => format!("object({})", fields.len()),
+        other => other.to_string(),
+    }
+}
+
+fn child_name(parent: Option<&str>, key: &str, index: bool) -> Option<String> {
+    parent.map(|parent| {
+        if index {
+            format!("{}[{}]", parent, key)
+        } else {
+            format!("{}.{}", parent, key)
+        }
+    })
+}
+
+/// `Variable` for `value` without `variablesReference`
+fn describe(
+    name: String,
+    value: &Value,
+    evaluate_name: Option<String>,
+    format: Option<&ValueFormat>,
+) -> Variable {
+    let (named, indexed) = match value {
+        Value::Object(fields) => (Some(fields.len() as i64), None),
+        Value::Array(items) => (None, Some(items.len() as i64)),
+        _ => (None, None),
+    };
+    Variable {
+        evaluate_name,
+        indexed_variables: indexed,
+        name,
+        named_variables: named,
+        presentation_hint: None,
+        type_: Some(type_name(value).to_string()),
+        value: format_value(value, format),
+        variables_reference: 0,
+    }
+}
+
+impl VariableTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// tree whose references are invalidated whenever `resumes` changes,
+    /// see [`HandleTable::tracking`]
+    pub fn tracking(resumes: Resumes) -> Self {
+        Self {
+            handles: HandleTable::tracking(resumes),
+        }
+    }
+
+    /// store `value` and return its `variablesReference`, e.g. for a `Scope`
+    ///
+    /// scalars have no children and return 0
+    pub fn insert(&mut self, value: Value, evaluate_name: Option<String>) -> i64 {
+        match value {
+            Value::Array(_) | Value::Object(_) => self.handles.insert(Node {
+                value,
+                evaluate_name,
+                children: HashMap::new(),
+            }),
+            _ => 0,
+        }
+    }
+
+    /// serialize `value` and store it, see [`insert`](Self::insert)
+    pub fn insert_serialize<T: Serialize>(
+        &mut self,
+        value: &T,
+        evaluate_name: Option<String>,
+    ) -> serde_json::Result<i64> {
+        Ok(self.insert(serde_json::to_value(value)?, evaluate_name))
+    }
+
+    /// build a `Variable` for `value`, registering it if it has children
+    pub fn variable(
+        &mut self,
+        name: impl Into<String>,
+        value: Value,
+        evaluate_name: Option<String>,
+        format: Option<&ValueFormat>,
+    ) -> Variable {
+        let mut variable = describe(name.into(), &value, evaluate_name.clone(), format);
+        variable.variables_reference = self.insert(value, evaluate_name);
+        variable
+    }
+
+    /// answer a `variables` request, applying `filter`, `start`, `count` and `format`
+    pub fn variables(
+        &mut self,
+        args: &VariablesArguments,
+    ) -> Result<VariablesResponseBody, HandleError> {
+        let parent = args.variables_reference;
+        let start = args.start.unwrap_or(0).max(0) as usize;
+        let count = match args.count {
+            Some(count) if count > 0 => count as usize,
+            _ => usize::MAX,
+        };
+        // clone only the requested page of children, not the whole node
+        let children: Vec<(String, Value, Option<String>, Option<i64>)> = {
+            let node = self.handles.get(parent)?;
+            let parent_name = node.evaluate_name.as_deref();
+            let child = |key: String, value: &Value, index: bool| {
+                let evaluate_name = child_name(parent_name, &key, index);
+                let reference = node.children.get(&key).copied();
+                (key, value.clone(), evaluate_name, reference)
+            };
+            match &node.value {
+                Value::Object(fields) if args.filter.as_deref() != Some(filter::INDEXED) => fields
+                    .iter()
+                    .skip(start)
+                    .take(count)
+                    .map(|(key, value)| child(key.clone(), value, false))
+                    .collect(),
+                Value::Array(items) if args.filter.as_deref() != Some(filter::NAMED) => items
+                    .iter()
+                    .enumerate()
+                    .skip(start)
+                    .take(count)
+                    .map(|(idx, value)| child(idx.to_string(), value, true))
+                    .collect(),
+                _ => vec![],
+            }
+        };
+        let variables = children
+            .into_iter()
+            .map(|(name, value, evaluate_name, reference)| {
+                let mut variable = describe(
+                    name.clone(),
+                    &value,
+                    evaluate_name.clone(),
+                    args.format.as_ref(),
+                );
+                variable.variables_reference = match reference {
+                    Some(reference) => reference,
+                    None => {
+                        let reference = self.insert(value, evaluate_name);
+                        if reference > 0 {
+                            if let Ok(parent) = self.handles.get_mut(parent) {
+                                parent.children.insert(name, reference);
+                            }
+                        }
+                        reference
+                    }
+                };
+                variable
+            })
+            .collect();
+        Ok(VariablesResponseBody {
+            error: None,
+            variables,
+        })
+    }
+
+    /// invalidate all references
+    pub fn reset(&mut self) {
+        self.handles.reset()
+    }
+
+    /// see [`HandleTable::observe`]
+    pub fn observe(&mut self, msg: &OneOf3<Request, Response, Event>) -> bool {
+        self.handles.observe(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn args(reference: i64) -> VariablesArguments {
+        VariablesArguments {
+            variables_reference: reference,
+            count: None,
+            start: None,
+            filter: None,
+            format: None,
+        }
+    }
+
+    fn references(body: &VariablesResponseBody) -> Vec<i64> {
+        body.variables
+            .iter()
+            .map(|v| v.variables_reference)
+            .collect()
+    }
+
+    #[test]
+    fn children_keep_reference() {
+        let mut tree = VariableTree::new();
+        let root = tree.insert(json!({"a": {"b": 1}, "c": [1, 2], "d": 3}), None);
+        let first = references(&tree.variables(&args(root)).unwrap());
+        let second = references(&tree.variables(&args(root)).unwrap());
+        assert_eq!(first, second);
+        assert_eq!(first[2], 0);
+        let before = tree.handles.len();
+        tree.variables(&args(root)).unwrap();
+        assert_eq!(tree.handles.len(), before);
+    }
+
+    #[test]
+    fn paging_and_filter() {
+        let mut tree = VariableTree::new();
+        let root = tree.insert(json!([10, 11, 12, 13]), Some("xs".to_string()));
+        let body = tree
+            .variables(&VariablesArguments {
+                start: Some(1),
+                count: Some(2),
+                ..args(root)
+            })
+            .unwrap();
+        let names: Vec<_> = body.variables.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["1", "2"]);
+        assert_eq!(body.variables[0].evaluate_name.as_deref(), Some("xs[1]"));
+        let body = tree
+            .variables(&VariablesArguments {
+                filter: Some(filter::NAMED.to_string()),
+                ..args(root)
+            })
+            .unwrap();
+        assert!(body.variables.is_empty());
+    }
+
+    #[test]
+    fn tracking_tree_invalidates_on_resume() {
+        let resumes = Resumes::new();
+        let mut tree = VariableTree::tracking(resumes.clone());
+        let root = tree.insert(json!({"a": 1}), None);
+        assert!(tree.variables(&args(root)).is_ok());
+        resumes.bump();
+        assert_eq!(
+            tree.variables(&args(root)).unwrap_err(),
+            HandleError::Stale(root)
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! derive `FromReq` and `FromEvent` of dap-ty for adapter specific requests and events
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize, DapRequest)]
+//! #[dap(command = "myCustom", response = MyCustomResponseBody)]
+//! struct MyCustomArguments {
+//!     /// documented like any other field
+//!     verbose: bool,
+//! }
+//!
+//! #[derive(Serialize, Deserialize, DapEvent)]
+//! #[dap(event = "myEvent")]
+//! struct MyEventBody {
+//!     message: String,
+//! }
+//! ```
+//!
+//! usually used through `derive` feature of dap-ty, which re-exports both macros.
+//!
+//! [`macro@dap_handler`] turns an async fn into a route of dap-io `Router`, it is re-exported
+//! by `derive` feature of dap-io.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, ToTokens};
+use syn::{
+    parse_macro_input, DeriveInput, FnArg, GenericArgument, ItemFn, LitStr, PathArguments,
+    ReturnType, Type, Visibility,
+};
+
+/// `#[dap(..)]` attributes of a type
+#[derive(Default)]
+struct DapAttrs {
+    command: Option<LitStr>,
+    response: Option<Type>,
+    event: Option<LitStr>,
+}
+
+impl DapAttrs {
+    fn parse(input: &DeriveInput) -> syn::Result<Self> {
+        let mut attrs = Self::default();
+        for attr in input.attrs.iter().filter(|a| a.path().is_ident("dap")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("command") {
+                    attrs.command = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("response") {
+                    attrs.response = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("event") {
+                    attrs.event = Some(meta.value()?.parse()?);
+                } else {
+                    return Err(meta.error("expected `command`, `response` or `event`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(attrs)
+    }
+}
+
+/// implement `FromReq`, command is set by `#[dap(command = "..")]`
+///
+/// response body type is set by `#[dap(response = Type)]`, `serde_json::Value` if omitted
+#[proc_macro_derive(DapRequest, attributes(dap))]
+pub fn derive_request(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_request(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// implement `FromEvent`, event name is set by `#[dap(event = "..")]`
+#[proc_macro_derive(DapEvent, attributes(dap))]
+pub fn derive_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_event(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_request(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = DapAttrs::parse(input)?;
+    if let Some(event) = &attrs.event {
+        return Err(syn::Error::new(
+            event.span(),
+            "`event` is only valid on DapEvent",
+        ));
+    }
+    let command = attrs.command.ok_or_else(|| {
+        syn::Error::new_spanned(&input.ident, "missing `#[dap(command = \"..\")]`")
+    })?;
+    let ret = match attrs.response {
+        Some(ty) => quote!(#ty),
+        None => quote!(::dap_ty::__private::serde_json::Value),
+    };
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::dap_ty::FromReq for #ident #ty_generics #where_clause {
+            const COMMAND: &'static str = #command;
+            type Ret = #ret;
+
+            fn from_req(
+                req: ::dap_ty::Request,
+            ) -> ::dap_ty::OneOf<
+                ::std::result::Result<(i64, Self), ::dap_ty::__private::serde_json::Error>,
+                ::dap_ty::Request,
+            > {
+                if <Self as ::dap_ty::FromReq>::can_cast(&req) {
+                    let ::dap_ty::Request { seq, arguments, .. } = req;
+                    ::dap_ty::OneOf::This(
+                        ::dap_ty::__private::serde_json::from_value(
+                            arguments.unwrap_or(::dap_ty::__private::serde_json::Value::Null),
+                        )
+                        .map(|params| (seq, params)),
+                    )
+                } else {
+                    ::dap_ty::OneOf::Other(req)
+                }
+            }
+        }
+
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// helper function for user do not need to remember
+            /// result type of a request
+            pub fn ret(result: #ret) -> #ret {
+                result
+            }
+        }
+    })
+}
+
+fn expand_event(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = DapAttrs::parse(input)?;
+    if let Some(command) = &attrs.command {
+        return Err(syn::Error::new(
+            command.span(),
+            "`command` is only valid on DapRequest",
+        ));
+    }
+    if let Some(response) = &attrs.response {
+        return Err(syn::Error::new_spanned(
+            response,
+            "events have no response, `response` is only valid on DapRequest",
+        ));
+    }
+    let event = attrs
+        .event
+        .ok_or_else(|| syn::Error::new_spanned(&input.ident, "missing `#[dap(event = \"..\")]`"))?;
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::dap_ty::FromEvent for #ident #ty_generics #where_clause {
+            const EVENT: &'static str = #event;
+
+            fn from_event(
+                event: ::dap_ty::Event,
+            ) -> ::dap_ty::OneOf<
+                ::std::result::Result<(i64, Self), ::dap_ty::__private::serde_json::Error>,
+                ::dap_ty::Event,
+            > {
+                if <Self as ::dap_ty::FromEvent>::can_cast(&event) {
+                    let ::dap_ty::Event { seq, body, .. } = event;
+                    ::dap_ty::OneOf::This(
+                        ::dap_ty::__private::serde_json::from_value(
+                            body.unwrap_or(::dap_ty::__private::serde_json::Value::Null),
+                        )
+                        .map(|params| (seq, params)),
+                    )
+                } else {
+                    ::dap_ty::OneOf::Other(event)
+                }
+            }
+        }
+    })
+}
+
+/// turn `async fn(ctx: &Ctx, args: Args) -> Result<Body, E>` into a route of dap-io `Router`
+///
+/// a unit struct of the same name implementing `Route` replaces the function, command is
+/// inferred from `FromReq::COMMAND` of `Args`. `ctx` may also be taken by value, `E` must
+/// implement `Display` and is sent to client as error message.
+///
+/// ```ignore
+/// #[dap_handler]
+/// async fn on_stack_trace(ctx: &Ctx, args: StackTraceArguments) -> Result<StackTraceResponseBody> {
+///     ..
+/// }
+///
+/// let router = Router::new().handler(on_stack_trace);
+/// ```
+#[proc_macro_attribute]
+pub fn dap_handler(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    if !attr.is_empty() {
+        let attr = TokenStream2::from(attr);
+        return syn::Error::new_spanned(attr, "dap_handler takes no arguments")
+            .into_compile_error()
+            .into();
+    }
+    expand_handler(item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// `T` of `Result<T, ..>` or of an alias like `Result<T>`
+fn ok_type(output: &ReturnType) -> syn::Result<&Type> {
+    let err = || syn::Error::new_spanned(output, "dap_handler must return `Result<T, E>`");
+    let ty = match output {
+        ReturnType::Type(_, ty) => ty,
+        ReturnType::Default => return Err(err()),
+    };
+    let segment = match ty.as_ref() {
+        Type::Path(path) => path.path.segments.last().ok_or_else(err)?,
+        _ => return Err(err()),
+    };
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first() {
+            Some(GenericArgument::Type(ty)) => Ok(ty),
+            _ => Err(err()),
+        },
+        _ => Err(err()),
+    }
+}
+
+fn expand_handler(item: ItemFn) -> syn::Result<TokenStream2> {
+    let sig = &item.sig;
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            sig.fn_token,
+            "dap_handler must be async",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "dap_handler can not be generic",
+        ));
+    }
+    let mut inputs = sig.inputs.iter().map(|arg| match arg {
+        FnArg::Typed(pat) => Ok(pat.ty.as_ref()),
+        FnArg::Receiver(recv) => Err(syn::Error::new_spanned(
+            recv,
+            "dap_handler can not take self",
+        )),
+    });
+    let (ctx_ty, args_ty) = match (inputs.next(), inputs.next(), inputs.next()) {
+        (Some(ctx), Some(args), None) => (ctx?, args?),
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &sig.inputs,
+                "dap_handler takes `(ctx: &Ctx, args: Args)`",
+            ))
+        }
+    };
+    let ctx = match ctx_ty {
+        Type::Reference(_) => quote!(&ctx),
+        _ => quote!(ctx),
+    };
+    let ok_ty = ok_type(&sig.output)?;
+    let vis = &item.vis;
+    let name = &sig.ident;
+    // docs go to the struct, other attributes stay on the function
+    let (docs, attrs): (Vec<_>, Vec<_>) = item
+        .attrs
+        .iter()
+        .cloned()
+        .partition(|a| a.path().is_ident("doc"));
+    let func = ItemFn {
+        attrs,
+        vis: Visibility::Inherited,
+        ..item.clone()
+    }
+    .to_token_stream();
+    Ok(quote! {
+        #(#docs)*
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Clone, Copy, Default)]
+        #vis struct #name;
+
+        impl ::dap_io::router::Route for #name {
+            type Args = #args_ty;
+            type Output = #ok_ty;
+
+            fn call(
+                &self,
+                ctx: ::dap_io::Ctx,
+                args: Self::Args,
+            ) -> ::dap_io::server::BoxFuture<::std::result::Result<Self::Output, ::std::string::String>>
+            {
+                #func
+                ::std::boxed::Box::pin(async move {
+                    #name(#ctx, args)
+                        .await
+                        .map_err(|e| ::std::string::ToString::to_string(&e))
+                })
+            }
+        }
+    })
+}
@@ -1,9 +1,8 @@
 use clap::Parser;
-use dap_io::AsyncCodec;
+use dap_io::{AsyncCodec, DapResult};
 use dap_ty::{Capabilities, FromReq, InitializeRequestArguments, OneOf, OneOf3, Request, Response};
 use tokio::{net::TcpStream, sync::Mutex};
 
-pub use std::io::Result as IOResult;
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Parser)]
@@ -23,7 +22,7 @@ async fn params_error(
     _server: Arc<Mutex<&mut Server>>,
     _seq: i64,
     e: serde_json::Error,
-) -> IOResult<()> {
+) -> DapResult<()> {
     // server
     // .lock()
     // .await
@@ -52,15 +51,16 @@ impl Server {
         }
     }
 
-    pub async fn receive(&mut self) -> IOResult<()> {
+    pub async fn receive(&mut self) -> DapResult<()> {
         match self.codec.receive().await? {
-            OneOf3::This(req) => self.on_req(req).await,
-            OneOf3::Among(_resp) => todo!(),
-            OneOf3::Other(_event) => todo!(),
+            Some(OneOf3::This(req)) => self.on_req(req).await,
+            Some(OneOf3::Among(_resp)) => todo!(),
+            Some(OneOf3::Other(_event)) => todo!(),
+            None => Ok(()),
         }
     }
 
-    pub async fn on_req(&mut self, req: Request) -> IOResult<()> {
+    pub async fn on_req(&mut self, req: Request) -> DapResult<()> {
         req.with(Arc::new(Mutex::new(self)), params_error)
             .async_then(|ctx, seq, _: InitializeRequestArguments| async move {
                 let ctx = &mut ctx.lock().await;
@@ -1,173 +1,479 @@
 use dap_ty::{Event, OneOf3, Request, Response};
 use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
 
-type IOResult<T> = std::io::Result<T>;
+use crate::error::{DapError, DapResult};
+use crate::trace::{Direction, Tracer};
+use crate::utils::{CodecState, Frame};
 
-use crate::utils::CodecState;
+/// read timeout is reported as `WouldBlock` on unix and `TimedOut` on windows
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+/// stream whose reads can time out, see [`Codec::with_read_timeout`]
+pub trait SetReadTimeout {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl SetReadTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl SetReadTimeout for std::os::unix::net::UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+}
 
-pub struct Codec<S: Read + Write> {
+type SetTimeout<S> = fn(&S, Option<Duration>) -> std::io::Result<()>;
+
+/// protocol message reader/writer
+///
+/// `S` only needs to be `Read` for receiving and `Write` for sending, so
+/// one direction of a pipe, e.g. stdin or stdout, can be used alone
+///
+/// to detect a dead peer, use [`with_read_timeout`](Self::with_read_timeout) and
+/// [`with_idle_timeout`](Self::with_idle_timeout) on a [`SetReadTimeout`] stream, or set
+/// a read timeout on any other stream yourself, expiry is reported as [`DapError::Timeout`]
+pub struct Codec<S> {
     stream: S,
     state: CodecState,
+    read_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    /// set by `with_*_timeout`, stream timeout is switched between idle and read timeout
+    set_timeout: Option<SetTimeout<S>>,
+    /// timeout currently set on stream, `None` if unknown
+    applied: Option<Option<Duration>>,
 }
 
-impl<S: Read + Write> Codec<S> {
+impl<S> Codec<S> {
     pub fn new(stream: S) -> Self {
         Self {
             stream,
             state: CodecState::default(),
+            read_timeout: None,
+            idle_timeout: None,
+            set_timeout: None,
+            applied: None,
         }
     }
 
+    /// record every message to `tracer`, overriding `DAP_TRACE` environment variable
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.state.tracer = Some(tracer);
+        self
+    }
+
+    /// reject messages larger than `max` bytes, default is 64 MiB
+    pub fn with_max_content_length(mut self, max: usize) -> Self {
+        self.state.max_content_length = max;
+        self
+    }
+
     /// get mutable ref of underlying stream
     pub fn stream_mut(&mut self) -> &mut S {
         &mut self.stream
     }
+}
 
-    fn poll(&mut self) -> IOResult<usize> {
-        let state = &mut self.state;
-        let count = self.stream.read(&mut state.read_buf)?;
-        if count == 0 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::ConnectionAborted,
-                "read eof",
-            ));
-        }
-        state.read_data.extend_from_slice(&state.read_buf[..count]);
-        Ok(count)
+impl<S: SetReadTimeout> Codec<S> {
+    /// fail with [`DapError::Timeout`] if a started message is not complete within `timeout`
+    /// of silence
+    ///
+    /// replaces read timeout of the stream
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self.set_timeout = Some(S::set_read_timeout);
+        self
     }
 
-    fn consume_body(&mut self) -> IOResult<OneOf3<Request, Response, Event>> {
-        self.state
-            .consume_body()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    /// fail with [`DapError::Timeout`] if no message starts within `timeout`
+    ///
+    /// replaces read timeout of the stream
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self.set_timeout = Some(S::set_read_timeout);
+        self
     }
+}
 
-    /// read message from peer
+impl<S: Read> Codec<S> {
+    /// read more data, return false on clean eof
+    ///
+    /// `idle` tells whether no part of next message has been read yet
+    fn poll(&mut self, idle: bool) -> DapResult<bool> {
+        let (timeout, what) = if idle {
+            (self.idle_timeout, "idle connection")
+        } else {
+            (self.read_timeout, "read")
+        };
+        if let Some(set_timeout) = self.set_timeout {
+            if self.applied != Some(timeout) {
+                set_timeout(&self.stream, timeout)?;
+                self.applied = Some(timeout);
+            }
+        }
+        let state = &mut self.state;
+        let count = match self.stream.read(&mut state.read_buf) {
+            Ok(count) => count,
+            Err(e) if is_timeout(&e) => return Err(DapError::Timeout(what.to_string())),
+            Err(e) => return Err(e.into()),
+        };
+        state.fill(count)
+    }
+
+    /// read message from peer, return `None` if peer closed connection between messages
     ///
     /// for server, most of times coming messages are request or notification,
-    /// at some rare case, there maybe a response, see [applyEdit](https://microsoft.github.io/language-server-protocol/specifications/specification-3-17/#workspace_applyEdit)
-    pub fn receive(&mut self) -> IOResult<OneOf3<Request, Response, Event>> {
+    /// at some rare case, there maybe a response, see [runInTerminal](https://microsoft.github.io/debug-adapter-protocol/specification#Reverse_Requests_RunInTerminal)
+    pub fn receive(&mut self) -> DapResult<Option<OneOf3<Request, Response, Event>>> {
         loop {
             if let Some(may_ok) = self.state.try_parse_header() {
-                may_ok.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                may_ok?;
                 break;
-            } else {
-                self.poll()?;
+            } else if !self.poll(self.state.read_data.is_empty())? {
+                return Ok(None);
+            }
+        }
+
+        while !self.state.body_ready() {
+            self.poll(false)?;
+        }
+
+        self.state.consume_body().map(Some)
+    }
+
+    /// read message from peer without parsing it, return `None` if peer closed
+    /// connection between messages
+    pub fn receive_frame(&mut self) -> DapResult<Option<Frame>> {
+        loop {
+            if let Some(may_ok) = self.state.try_parse_header() {
+                may_ok?;
+                break;
+            } else if !self.poll(self.state.read_data.is_empty())? {
+                return Ok(None);
             }
         }
 
         while !self.state.body_ready() {
-            self.poll()?;
+            self.poll(false)?;
         }
 
-        self.consume_body()
+        Ok(Some(self.state.consume_frame()))
     }
+}
 
+impl<S: Write> Codec<S> {
     /// write message to peer
-    pub fn send(&mut self, message: OneOf3<Request, Response, Event>) -> IOResult<()> {
-        let json_str = serde_json::to_string(&message)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    pub fn send(&mut self, message: OneOf3<Request, Response, Event>) -> DapResult<()> {
+        let json_str = serde_json::to_string(&message).map_err(DapError::Encode)?;
         let data = json_str.as_bytes();
+        self.state.trace(Direction::Outbound, data);
         self.stream
             .write_all(format!("Content-Length: {}\r\n\r\n", data.len(),).as_bytes())?;
         self.stream.write_all(data)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// write header and body of `frame` as they are
+    pub fn send_frame(&mut self, frame: &Frame) -> DapResult<()> {
+        self.state.trace(Direction::Outbound, &frame.body);
+        self.stream.write_all(&frame.header)?;
+        self.stream.write_all(&frame.body)?;
+        self.stream.flush()?;
         Ok(())
     }
 
     /// helper function to send request only
-    pub fn send_req(&mut self, message: Request) -> IOResult<()> {
+    pub fn send_req(&mut self, message: Request) -> DapResult<()> {
         self.send(OneOf3::This(message))
     }
 
     /// helper function to send response only
-    pub fn send_resp(&mut self, message: Response) -> IOResult<()> {
+    pub fn send_resp(&mut self, message: Response) -> DapResult<()> {
         self.send(OneOf3::Among(message))
     }
 
     /// helper function to send notification only
-    pub fn send_event(&mut self, message: Event) -> IOResult<()> {
+    pub fn send_event(&mut self, message: Event) -> DapResult<()> {
         self.send(OneOf3::Other(message))
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    #[test]
+    fn clean_eof_is_none() {
+        let input = framed(r#"{"seq":1,"type":"event","event":"initialized"}"#);
+        let mut codec = Codec::new(input.as_bytes());
+        assert!(matches!(codec.receive(), Ok(Some(OneOf3::Other(_)))));
+        assert!(matches!(codec.receive(), Ok(None)));
+        assert!(matches!(Codec::new(&b""[..]).receive(), Ok(None)));
+    }
+
+    #[test]
+    fn eof_inside_message_is_closed() {
+        for input in ["Content-Len", "Content-Length: 10\r\n\r\n{\"seq\""] {
+            let mut codec = Codec::new(input.as_bytes());
+            assert!(
+                matches!(codec.receive(), Err(DapError::Closed)),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn oversized_and_invalid_messages() {
+        let input = framed("{}");
+        let mut codec = Codec::new(input.as_bytes()).with_max_content_length(1);
+        assert!(matches!(
+            codec.receive(),
+            Err(DapError::FrameTooLarge { len: 2, max: 1 })
+        ));
+
+        let input = framed("{}");
+        let mut codec = Codec::new(input.as_bytes());
+        assert!(matches!(codec.receive(), Err(DapError::Json { raw, .. }) if raw == "{}"));
+
+        let mut codec = Codec::new(&b"Content-Length 2\r\n\r\n{}"[..]);
+        assert!(matches!(codec.receive(), Err(DapError::Header(_))));
+    }
+
+    #[test]
+    fn send_then_receive() {
+        let event = Event {
+            body: None,
+            event: "initialized".to_string(),
+            seq: 3,
+            type_: "event".to_string(),
+        };
+        let mut writer = Codec::new(Vec::new());
+        writer.send_event(event.clone()).unwrap();
+        let written = std::mem::take(writer.stream_mut());
+        let mut reader = Codec::new(written.as_slice());
+        assert!(matches!(reader.receive(), Ok(Some(OneOf3::Other(e))) if e == event));
+    }
+}
+
 #[cfg(feature = "ws")]
 mod ws_codec {
+    use std::io::Write;
     use std::net::TcpStream;
+    use std::sync::mpsc::{self, RecvTimeoutError};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     use dap_ty::{Event, OneOf3, Request, Response};
     use ws_tool::{
         codec::{default_handshake_handler, WsStringCodec},
+        errors::WsError,
         frame::OpCode,
         stream::WsStream,
         ClientBuilder, ServerBuilder,
     };
 
-    use super::IOResult;
+    use crate::error::{DapError, DapResult};
+    use crate::trace::{Direction, Tracer};
+
+    /// empty ping frame, clients must mask every frame they send
+    const CLIENT_PING: &[u8] = &[0x89, 0x80, 0, 0, 0, 0];
+    const SERVER_PING: &[u8] = &[0x89, 0x00];
+
+    /// whether `e` is the stream read timeout set by keepalive
+    fn is_timeout(e: &WsError) -> bool {
+        match e {
+            WsError::IOError(e) => e
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(super::is_timeout),
+            _ => false,
+        }
+    }
 
     pub struct WsCodec {
         ws: WsStringCodec<WsStream<TcpStream>>,
+        tracer: Option<Tracer>,
+        client: bool,
+        /// whether read timeout of stream is set by keepalive
+        keepalive: bool,
+        /// when last frame was received
+        last_seen: Arc<Mutex<Instant>>,
+        /// held while writing a frame, pings are written by another thread
+        writing: Arc<Mutex<()>>,
+        /// dropped to stop ping thread
+        _pinger: Option<mpsc::Sender<()>>,
     }
 
     impl WsCodec {
-        pub fn new_client<S: ToString>(addr: S) -> IOResult<Self> {
-            let ws = ClientBuilder::new(addr).connect(WsStringCodec::check_fn)?;
-            Ok(Self { ws })
+        pub fn new_client<S: ToString>(addr: S) -> DapResult<Self> {
+            let ws = ClientBuilder::new(addr)
+                .connect(WsStringCodec::check_fn)
+                .map_err(|e| DapError::Ws(e.to_string()))?;
+            Ok(Self {
+                ws,
+                tracer: Tracer::from_env(),
+                client: true,
+                keepalive: false,
+                last_seen: Arc::new(Mutex::new(Instant::now())),
+                writing: Arc::new(Mutex::new(())),
+                _pinger: None,
+            })
         }
 
-        pub fn new_server(stream: TcpStream) -> IOResult<Self> {
+        pub fn new_server(stream: TcpStream) -> DapResult<Self> {
             let ws =
-                ServerBuilder::accept(stream, default_handshake_handler, WsStringCodec::factory)?;
-            Ok(Self { ws })
+                ServerBuilder::accept(stream, default_handshake_handler, WsStringCodec::factory)
+                    .map_err(|e| DapError::Ws(e.to_string()))?;
+            Ok(Self {
+                ws,
+                tracer: Tracer::from_env(),
+                client: false,
+                keepalive: false,
+                last_seen: Arc::new(Mutex::new(Instant::now())),
+                writing: Arc::new(Mutex::new(())),
+                _pinger: None,
+            })
+        }
+
+        /// record every message to `tracer`, overriding `DAP_TRACE` environment variable
+        pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+            self.tracer = Some(tracer);
+            self
+        }
+
+        /// send ping when no frame arrived for `interval`, fail with [`DapError::Timeout`]
+        /// if peer stays silent for `timeout` after that
+        ///
+        /// pings are sent by a background thread, so a frame being received is never
+        /// interrupted. read timeout of underlying stream is set to `interval + timeout`,
+        /// a read timing out means peer is gone
+        pub fn with_keepalive(mut self, interval: Duration, timeout: Duration) -> DapResult<Self> {
+            let stream = self.stream_mut();
+            stream.set_read_timeout(Some(interval + timeout))?;
+            let mut pinger = stream.try_clone()?;
+            let ping = if self.client {
+                CLIENT_PING
+            } else {
+                SERVER_PING
+            };
+            let last_seen = self.last_seen.clone();
+            let writing = self.writing.clone();
+            let (stop, stopped) = mpsc::channel();
+            thread::spawn(move || {
+                let mut last_ping = Instant::now();
+                loop {
+                    let since = (*last_seen.lock().unwrap()).max(last_ping);
+                    let wait = interval.saturating_sub(since.elapsed());
+                    if stopped.recv_timeout(wait) != Err(RecvTimeoutError::Timeout) {
+                        return;
+                    }
+                    if last_seen.lock().unwrap().elapsed() < interval {
+                        continue;
+                    }
+                    let _writing = writing.lock().unwrap();
+                    if pinger.write_all(ping).is_err() {
+                        return;
+                    }
+                    last_ping = Instant::now();
+                }
+            });
+            self.keepalive = true;
+            self._pinger = Some(stop);
+            Ok(self)
+        }
+
+        fn trace(&self, dir: Direction, raw: &[u8]) {
+            if let Some(tracer) = &self.tracer {
+                tracer.record(dir, raw);
+            }
         }
 
         pub fn stream_mut(&mut self) -> &mut TcpStream {
             self.ws.stream_mut().stream_mut()
         }
 
-        pub fn receive(&mut self) -> IOResult<OneOf3<Request, Response, Event>> {
-            let msg = self.ws.receive()?;
-            if msg.code == OpCode::Close {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::ConnectionAborted,
-                    "peer send close",
-                ));
-            } else if msg.code == OpCode::Text {
-                let msg = serde_json::from_str(&msg.data).map_err(|e| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
-                })?;
-                Ok(msg)
-            } else {
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("unknown frame code {:?}", msg.code),
-                ))
+        /// read message from peer, return `None` if peer sent close frame
+        ///
+        /// ping and pong frames are handled here
+        pub fn receive(&mut self) -> DapResult<Option<OneOf3<Request, Response, Event>>> {
+            loop {
+                let msg = match self.ws.receive() {
+                    Ok(msg) => msg,
+                    Err(e) if self.keepalive && is_timeout(&e) => {
+                        return Err(DapError::Timeout("websocket keepalive".to_string()));
+                    }
+                    Err(e) => return Err(DapError::Ws(e.to_string())),
+                };
+                *self.last_seen.lock().unwrap() = Instant::now();
+                match msg.code {
+                    OpCode::Close => return Ok(None),
+                    OpCode::Text => {
+                        self.trace(Direction::Inbound, msg.data.as_bytes());
+                        let parsed =
+                            serde_json::from_str(&msg.data).map_err(|source| DapError::Json {
+                                raw: msg.data,
+                                source,
+                            })?;
+                        return Ok(Some(parsed));
+                    }
+                    OpCode::Ping => {
+                        let _writing = self.writing.lock().unwrap();
+                        self.ws
+                            .send((OpCode::Pong, msg.data))
+                            .map_err(|e| DapError::Ws(e.to_string()))?
+                    }
+                    OpCode::Pong => {}
+                    code => return Err(DapError::Ws(format!("unknown frame code {:?}", code))),
+                }
             }
         }
 
-        pub fn close(&mut self, status: u16, msg: String) -> IOResult<()> {
-            self.ws.send((status, msg))?;
+        pub fn close(&mut self, status: u16, msg: String) -> DapResult<()> {
+            let _writing = self.writing.lock().unwrap();
+            self.ws
+                .send((status, msg))
+                .map_err(|e| DapError::Ws(e.to_string()))?;
             Ok(())
         }
 
-        pub fn send(&mut self, message: OneOf3<Request, Response, Event>) -> IOResult<()> {
-            let json_str = serde_json::to_string(&message)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-            self.ws.send(json_str)?;
+        pub fn send(&mut self, message: OneOf3<Request, Response, Event>) -> DapResult<()> {
+            let json_str = serde_json::to_string(&message).map_err(DapError::Encode)?;
+            self.trace(Direction::Outbound, json_str.as_bytes());
+            let _writing = self.writing.lock().unwrap();
+            self.ws
+                .send(json_str)
+                .map_err(|e| DapError::Ws(e.to_string()))?;
             Ok(())
         }
 
         /// helper function to send request only
-        pub fn send_req(&mut self, message: Request) -> IOResult<()> {
+        pub fn send_req(&mut self, message: Request) -> DapResult<()> {
             self.send(OneOf3::This(message))
         }
 
         /// helper function to send response only
-        pub fn send_resp(&mut self, message: Response) -> IOResult<()> {
+        pub fn send_resp(&mut self, message: Response) -> DapResult<()> {
             self.send(OneOf3::Among(message))
         }
 
         /// helper function to send notification only
-        pub fn send_event(&mut self, message: Event) -> IOResult<()> {
+        pub fn send_event(&mut self, message: Event) -> DapResult<()> {
             self.send(OneOf3::Other(message))
         }
     }
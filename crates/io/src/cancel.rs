@@ -0,0 +1,144 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use dap_ty::{Request, Response};
+use tokio::sync::Notify;
+
+/// `message` of a response to a cancelled request
+pub const CANCELLED: &str = "cancelled";
+
+#[derive(Debug, Default)]
+struct TokenInner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+/// set when client sends `cancel` for a request or progress, cheap to clone
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<TokenInner>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// wait until token is cancelled, useful in `tokio::select!`
+    pub async fn cancelled(&self) {
+        loop {
+            // register before checking flag so a concurrent cancel is not missed
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// error response with [`CANCELLED`] message
+pub fn cancelled_response(req: &Request) -> Response {
+    Response::err::<(), _>(req.seq, &req.command, CANCELLED.to_string(), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use dap_ty::{CancelArguments, EvaluateArguments, FromReq};
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::server::tests::{connect, evaluate, initialize, ok};
+    use crate::server::{BoxFuture, Ctx, Policy};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// `evaluate` reports `started` and then `cancelled` once its token is cancelled
+    fn handler(
+        events: mpsc::UnboundedSender<&'static str>,
+    ) -> impl Fn(Ctx, Request) -> BoxFuture<Response> + Send + Sync + 'static {
+        move |ctx: Ctx, req: Request| {
+            let events = events.clone();
+            Box::pin(async move {
+                if EvaluateArguments::can_cast(&req) {
+                    events.send("started").ok();
+                    ctx.cancel_token(req.seq).cancelled().await;
+                    events.send("cancelled").ok();
+                }
+                ok(&req)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn cancel_running_request() {
+        let (tx, mut events) = mpsc::unbounded_channel();
+        let (client, server) = connect(handler(tx), Policy::default());
+        initialize(&client).await;
+
+        let pending = client.request(evaluate("loop")).timeout(WAIT);
+        assert_eq!(events.recv().await, Some("started"));
+        let args = CancelArguments {
+            request_id: Some(pending.seq()),
+            progress_id: None,
+        };
+        let cancel = client.request(args).timeout(WAIT).await.unwrap();
+        assert!(cancel.success);
+        assert_eq!(events.recv().await, Some("cancelled"));
+
+        let resp = pending.await.unwrap();
+        assert!(!resp.success);
+        assert_eq!(resp.message.as_deref(), Some(CANCELLED));
+        assert_eq!(resp.command, EvaluateArguments::COMMAND);
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropped_request_is_cancelled() {
+        let (tx, mut events) = mpsc::unbounded_channel();
+        let (client, server) = connect(handler(tx), Policy::default());
+        initialize(&client).await;
+
+        let pending = client.request(evaluate("loop")).cancel_on_drop(true);
+        assert_eq!(events.recv().await, Some("started"));
+        drop(pending);
+        let cancelled = tokio::time::timeout(WAIT, events.recv()).await.unwrap();
+        assert_eq!(cancelled, Some("cancelled"));
+
+        // without cancel_on_drop nothing is sent
+        let pending = client.request(evaluate("loop"));
+        assert_eq!(events.recv().await, Some("started"));
+        drop(pending);
+        let idle = tokio::time::timeout(Duration::from_millis(100), events.recv()).await;
+        assert!(idle.is_err());
+        // second evaluate never ends
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn token_wakes_waiters() {
+        let token = CancelToken::new();
+        let waiter = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!token.is_cancelled());
+        token.cancel();
+        tokio::time::timeout(WAIT, waiter).await.unwrap().unwrap();
+        // already cancelled token returns at once
+        token.cancelled().await;
+    }
+}
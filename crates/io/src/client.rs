@@ -0,0 +1,608 @@
+//! async client side of a connection, e.g. for tests or tools driving an adapter
+//!
+//! [`Client::request`] returns a [`PendingRequest`] future resolving to adapter's response.
+//! with [`cancel_on_drop`](PendingRequest::cancel_on_drop), dropping the future before it
+//! resolves sends a `cancel` request for it. requests fail with [`DapError::Timeout`] if
+//! adapter does not answer within [`with_timeout`](Client::with_timeout).
+//!
+//! events are broadcast to every [`EventStream`] from [`Client::events`], which is also a
+//! [`Stream`].
+use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use dap_ty::{CancelArguments, Event, FromEvent, FromReq, OneOf, OneOf3, Request, Response};
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::Stream;
+
+use crate::error::{DapError, DapResult};
+use crate::server::response_body;
+use crate::AsyncCodec;
+
+type Message = OneOf3<Request, Response, Event>;
+
+type RequestHandler = Arc<dyn Fn(Request) -> Response + Send + Sync>;
+
+/// events buffered for each [`EventStream`], a slower stream skips older ones
+const EVENT_CAPACITY: usize = 256;
+
+struct Outbox {
+    seq: i64,
+    /// `None` after connection closed
+    tx: Option<mpsc::UnboundedSender<Message>>,
+    /// set if connection was closed because adapter stopped responding
+    timed_out: bool,
+}
+
+struct Inner {
+    outbox: Mutex<Outbox>,
+    /// requests waiting for response, keyed by request seq
+    pending: Mutex<HashMap<i64, oneshot::Sender<Response>>>,
+    /// `None` after connection closed
+    events: Mutex<Option<broadcast::Sender<Event>>>,
+    /// answers reverse requests, see [`Client::on_request`]
+    on_request: Mutex<Option<RequestHandler>>,
+}
+
+/// handle to a connection with an adapter, cheap to clone
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+    /// default timeout of requests sent through this handle
+    timeout: Option<Duration>,
+}
+
+impl Client {
+    /// start reading and writing in background tasks, must be called inside a tokio runtime
+    ///
+    /// reverse requests from adapter are answered with an error response, unless a handler
+    /// is set with [`on_request`](Self::on_request). connection is shut down when every clone of client is dropped
+    pub fn spawn<R, W>(reader: R, writer: W) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        Self::spawn_codec(AsyncCodec::new(reader), AsyncCodec::new(writer))
+    }
+
+    /// like [`spawn`](Self::spawn) with configured codecs, e.g. with idle timeout of
+    /// reader to detect a dead adapter
+    pub fn spawn_codec<R, W>(mut reader: AsyncCodec<R>, mut writer: AsyncCodec<W>) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Self {
+            inner: Arc::new(Inner {
+                outbox: Mutex::new(Outbox {
+                    seq: 0,
+                    tx: Some(tx),
+                    timed_out: false,
+                }),
+                pending: Mutex::new(HashMap::new()),
+                events: Mutex::new(Some(broadcast::channel(EVENT_CAPACITY).0)),
+                on_request: Mutex::new(None),
+            }),
+            timeout: None,
+        };
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                if let Err(e) = writer.send(msg).await {
+                    tracing::warn!("failed to write to adapter: {}", e);
+                    break;
+                }
+            }
+            writer.stream_mut().shutdown().await.ok();
+        });
+        // reader must not keep client alive
+        let reading = Arc::downgrade(&client.inner);
+        tokio::spawn(async move {
+            loop {
+                let msg = reader.receive().await;
+                let client = match Weak::upgrade(&reading) {
+                    Some(inner) => Client {
+                        inner,
+                        timeout: None,
+                    },
+                    None => break,
+                };
+                match msg {
+                    Ok(Some(msg)) => client.on_message(msg),
+                    Ok(None) => break client.close(),
+                    Err(e) => {
+                        tracing::warn!("failed to read from adapter: {}", e);
+                        client.inner.outbox.lock().unwrap().timed_out = e.is_timeout();
+                        break client.close();
+                    }
+                }
+            }
+        });
+        client
+    }
+
+    /// fail requests sent through returned handle with [`DapError::Timeout`] if adapter
+    /// does not answer within `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// answer reverse requests from adapter with `handler`, `seq` of returned response is
+    /// assigned when it is sent
+    pub fn on_request<F>(&self, handler: F)
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        *self.inner.on_request.lock().unwrap() = Some(Arc::new(handler));
+    }
+
+    fn on_message(&self, msg: Message) {
+        match msg {
+            OneOf3::Among(resp) => {
+                match self.inner.pending.lock().unwrap().remove(&resp.request_seq) {
+                    Some(tx) => {
+                        tx.send(resp).ok();
+                    }
+                    None => {
+                        tracing::debug!("ignore response to {} {}", resp.request_seq, resp.command)
+                    }
+                }
+            }
+            OneOf3::This(req) => {
+                let handler = self.inner.on_request.lock().unwrap().clone();
+                let resp = match handler {
+                    Some(handler) => handler(req),
+                    None => Response::err::<(), _>(
+                        req.seq,
+                        &req.command,
+                        format!("{} is not supported", req.command),
+                        None,
+                    ),
+                };
+                self.send(OneOf3::Among(resp)).ok();
+            }
+            OneOf3::Other(event) => {
+                if let Some(events) = self.inner.events.lock().unwrap().as_ref() {
+                    // no subscriber is not an error
+                    events.send(event).ok();
+                }
+            }
+        }
+    }
+
+    /// error of requests on a closed connection
+    fn closed_error(&self) -> DapError {
+        if self.inner.outbox.lock().unwrap().timed_out {
+            DapError::Timeout("reading from adapter".to_string())
+        } else {
+            DapError::Closed
+        }
+    }
+
+    /// fail pending requests, end event streams and stop writing
+    fn close(&self) {
+        self.inner.outbox.lock().unwrap().tx = None;
+        self.inner.events.lock().unwrap().take();
+        self.inner.pending.lock().unwrap().clear();
+    }
+
+    /// send a message, `seq` is assigned here and returned
+    pub fn send(&self, mut msg: Message) -> DapResult<i64> {
+        let mut outbox = self.inner.outbox.lock().unwrap();
+        outbox.seq += 1;
+        let seq = outbox.seq;
+        match &mut msg {
+            OneOf3::This(req) => req.seq = seq,
+            OneOf3::Among(resp) => resp.seq = seq,
+            OneOf3::Other(event) => event.seq = seq,
+        }
+        let sent = outbox.tx.as_ref().map(|tx| tx.send(msg).is_ok());
+        drop(outbox);
+        match sent {
+            Some(true) => Ok(seq),
+            _ => Err(self.closed_error()),
+        }
+    }
+
+    /// send a request, returned future resolves to adapter's response
+    pub fn request<T: FromReq>(&self, args: T) -> PendingRequest {
+        let (tx, rx) = oneshot::channel();
+        // hold pending lock so response can not arrive before it is registered
+        let mut pending = self.inner.pending.lock().unwrap();
+        let (seq, rx) = match self.send(OneOf3::This(args.into_req(0))) {
+            Ok(seq) => {
+                pending.insert(seq, tx);
+                (seq, Ok(rx))
+            }
+            Err(e) => (0, Err(Some(e))),
+        };
+        PendingRequest {
+            seq,
+            rx,
+            client: self.clone(),
+            done: false,
+            cancel_on_drop: false,
+            deadline: self.timeout.map(|t| (t, Box::pin(tokio::time::sleep(t)))),
+        }
+    }
+
+    /// send a request and parse response body, error response is returned as
+    /// [`DapError::Protocol`]
+    pub async fn call<T: FromReq>(&self, args: T) -> DapResult<T::Ret>
+    where
+        T::Ret: DeserializeOwned,
+    {
+        response_body(self.request(args).await?)
+    }
+
+    /// subscribe to events of type `T`, events received before this call are not seen
+    pub fn events<T: FromEvent + DeserializeOwned>(&self) -> EventStream<T> {
+        EventStream {
+            rx: self
+                .inner
+                .events
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|e| BroadcastStream::new(e.subscribe())),
+            _event: PhantomData,
+        }
+    }
+
+    /// wait for next event of type `T`
+    ///
+    /// only events received after this call count, subscribe with [`events`](Self::events)
+    /// before sending the request that triggers the event to not miss it
+    pub async fn wait_for<T: FromEvent + DeserializeOwned>(
+        &self,
+        timeout: Duration,
+    ) -> DapResult<T> {
+        self.events::<T>().wait(timeout).await
+    }
+
+    /// ask adapter to cancel request `seq`
+    pub fn cancel(&self, seq: i64) -> DapResult<()> {
+        let args = CancelArguments {
+            request_id: Some(seq),
+            progress_id: None,
+        };
+        self.send(OneOf3::This(args.into_req(0))).map(|_| ())
+    }
+
+    /// ask adapter to cancel progress `id`
+    pub fn cancel_progress(&self, id: &str) -> DapResult<()> {
+        let args = CancelArguments {
+            request_id: None,
+            progress_id: Some(id.to_string()),
+        };
+        self.send(OneOf3::This(args.into_req(0))).map(|_| ())
+    }
+}
+
+/// response future of [`Client::request`]
+pub struct PendingRequest {
+    seq: i64,
+    /// `Err` if request could not be sent
+    rx: Result<oneshot::Receiver<Response>, Option<DapError>>,
+    client: Client,
+    done: bool,
+    cancel_on_drop: bool,
+    deadline: Option<(Duration, Pin<Box<tokio::time::Sleep>>)>,
+}
+
+impl PendingRequest {
+    /// seq of sent request
+    pub fn seq(&self) -> i64 {
+        self.seq
+    }
+
+    /// send `cancel` if this future is dropped before response arrives or times out
+    pub fn cancel_on_drop(mut self, enable: bool) -> Self {
+        self.cancel_on_drop = enable;
+        self
+    }
+
+    /// fail with [`DapError::Timeout`] if response does not arrive within `timeout`,
+    /// overriding timeout of client
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some((timeout, Box::pin(tokio::time::sleep(timeout))));
+        self
+    }
+
+    /// stop waiting for response, send `cancel` if enabled
+    fn abandon(&mut self) {
+        self.done = true;
+        self.client.inner.pending.lock().unwrap().remove(&self.seq);
+        if self.cancel_on_drop {
+            if let Err(e) = self.client.cancel(self.seq) {
+                tracing::debug!("failed to cancel request {}: {}", self.seq, e);
+            }
+        }
+    }
+}
+
+impl Future for PendingRequest {
+    type Output = DapResult<Response>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // `None` error means connection closed
+        let polled = match &mut self.rx {
+            Ok(rx) => match Pin::new(rx).poll(cx) {
+                Poll::Ready(Ok(resp)) => Poll::Ready(Ok(resp)),
+                Poll::Ready(Err(_)) => Poll::Ready(Err(None)),
+                Poll::Pending => Poll::Pending,
+            },
+            Err(e) => Poll::Ready(Err(e.take())),
+        };
+        let polled = polled.map(|r| r.map_err(|e| e.unwrap_or_else(|| self.client.closed_error())));
+        if polled.is_ready() {
+            self.done = true;
+            return polled;
+        }
+        let expired = match &mut self.deadline {
+            Some((timeout, sleep)) => sleep.as_mut().poll(cx).is_ready().then_some(*timeout),
+            None => None,
+        };
+        match expired {
+            Some(timeout) => {
+                self.abandon();
+                Poll::Ready(Err(DapError::Timeout(format!(
+                    "request {} after {:?}",
+                    self.seq, timeout
+                ))))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        if self.done || self.rx.is_err() {
+            return;
+        }
+        self.abandon();
+    }
+}
+
+/// events of type `T` received by a [`Client`]
+///
+/// events that fail to parse are logged and skipped
+pub struct EventStream<T> {
+    /// `None` if subscribed after connection closed
+    rx: Option<BroadcastStream<Event>>,
+    _event: PhantomData<fn() -> T>,
+}
+
+impl<T: FromEvent + DeserializeOwned> Stream for EventStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let rx = match self.rx.as_mut() {
+            Some(rx) => rx,
+            None => return Poll::Ready(None),
+        };
+        loop {
+            let event = match std::task::ready!(Pin::new(&mut *rx).poll_next(cx)) {
+                Some(Ok(event)) => event,
+                Some(Err(BroadcastStreamRecvError::Lagged(n))) => {
+                    tracing::warn!("{} stream lagged, skipped {} events", T::EVENT, n);
+                    continue;
+                }
+                None => return Poll::Ready(None),
+            };
+            match T::from_event(event) {
+                OneOf::This(Ok((_, body))) => return Poll::Ready(Some(body)),
+                OneOf::This(Err(e)) => tracing::warn!("invalid {} event: {}", T::EVENT, e),
+                OneOf::Other(_) => {}
+            }
+        }
+    }
+}
+
+impl<T: FromEvent + DeserializeOwned> EventStream<T> {
+    /// next event, `None` after connection closed
+    pub async fn next(&mut self) -> Option<T> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// next event within `timeout`
+    pub async fn wait(&mut self, timeout: Duration) -> DapResult<T> {
+        match tokio::time::timeout(timeout, self.next()).await {
+            Ok(Some(body)) => Ok(body),
+            Ok(None) => Err(DapError::Closed),
+            Err(_) => Err(DapError::Timeout(format!(
+                "waiting for {} event after {:?}",
+                T::EVENT,
+                timeout
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dap_ty::{OutputEventBody, TerminatedEventBody, ThreadsRequestArguments};
+    use serde_json::json;
+    use tokio::io::DuplexStream;
+    use tokio_stream::StreamExt;
+
+    use super::*;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// client and a codec playing adapter on the other end of an in-memory pipe
+    fn connect() -> (Client, AsyncCodec<DuplexStream>) {
+        let (client_io, adapter_io) = tokio::io::duplex(64 * 1024);
+        let (reader, writer) = tokio::io::split(client_io);
+        (Client::spawn(reader, writer), AsyncCodec::new(adapter_io))
+    }
+
+    /// answer next request read by `adapter`
+    async fn answer(adapter: &mut AsyncCodec<DuplexStream>) -> i64 {
+        let req = match adapter.receive().await {
+            Ok(Some(OneOf3::This(req))) => req,
+            other => panic!("expected request, got {:?}", other),
+        };
+        adapter
+            .send_resp(Response::ok_with::<(), _>(req.seq, &req.command, None))
+            .await
+            .unwrap();
+        req.seq
+    }
+
+    fn output(text: &str) -> Event {
+        OutputEventBody {
+            output: text.to_string(),
+            ..Default::default()
+        }
+        .into_event(0)
+    }
+
+    #[tokio::test]
+    async fn stream_yields_events_of_its_type() {
+        let (client, mut adapter) = connect();
+        let outputs = client.events::<OutputEventBody>();
+        adapter.send_event(output("a")).await.unwrap();
+        adapter
+            .send_event(TerminatedEventBody::default().into_event(0))
+            .await
+            .unwrap();
+        // invalid body is skipped
+        let mut invalid = output("x");
+        invalid.body = Some(json!({"output": 1}));
+        adapter.send_event(invalid).await.unwrap();
+        adapter.send_event(output("b")).await.unwrap();
+        drop(adapter);
+
+        let texts: Vec<_> = outputs.map(|body| body.output).collect().await;
+        assert_eq!(texts, ["a", "b"]);
+        // subscribed after connection closed
+        assert!(client.events::<OutputEventBody>().next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn lagged_stream_skips_oldest_events() {
+        let (client, mut adapter) = connect();
+        let mut outputs = client.events::<OutputEventBody>();
+        let mut done = client.events::<TerminatedEventBody>();
+        let total = EVENT_CAPACITY + 50;
+        for i in 0..total {
+            adapter.send_event(output(&i.to_string())).await.unwrap();
+        }
+        adapter
+            .send_event(TerminatedEventBody::default().into_event(0))
+            .await
+            .unwrap();
+        done.wait(WAIT).await.unwrap();
+        drop(adapter);
+
+        let mut received = vec![];
+        while let Some(body) = outputs.next().await {
+            received.push(body.output.parse::<usize>().unwrap());
+        }
+        assert!(received.len() < total);
+        assert_eq!(received.last(), Some(&(total - 1)));
+        let first = received[0];
+        assert!(first > 0);
+        assert_eq!(received, (first..total).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn wait_for_event() {
+        let (client, mut adapter) = connect();
+        let waiting = tokio::spawn({
+            let client = client.clone();
+            async move { client.wait_for::<TerminatedEventBody>(WAIT).await }
+        });
+        // let waiter subscribe before event is sent
+        tokio::task::yield_now().await;
+        adapter.send_event(output("noise")).await.unwrap();
+        let terminated = TerminatedEventBody {
+            restart: Some(json!({"again": true})),
+            ..Default::default()
+        };
+        adapter.send_event(terminated.into_event(0)).await.unwrap();
+        let body = waiting.await.unwrap().unwrap();
+        assert_eq!(body.restart, Some(json!({"again": true})));
+
+        let timeout = client
+            .wait_for::<TerminatedEventBody>(Duration::from_millis(20))
+            .await;
+        assert!(matches!(timeout, Err(DapError::Timeout(_))));
+
+        drop(adapter);
+        let closed = client.wait_for::<TerminatedEventBody>(WAIT).await;
+        assert!(matches!(closed, Err(DapError::Closed)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn client_timeout_fails_unanswered_requests() {
+        let (client, mut adapter) = connect();
+        let timed = client.clone().with_timeout(Duration::from_secs(1));
+        let start = tokio::time::Instant::now();
+        let pending = timed.request(ThreadsRequestArguments::default());
+        let seq = pending.seq();
+        assert!(matches!(pending.await, Err(DapError::Timeout(_))));
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        assert!(client.inner.pending.lock().unwrap().is_empty());
+
+        // late response is dropped, connection keeps working
+        assert_eq!(answer(&mut adapter).await, seq);
+        let answered = tokio::spawn(timed.request(ThreadsRequestArguments::default()));
+        let seq = answer(&mut adapter).await;
+        assert_eq!(answered.await.unwrap().unwrap().request_seq, seq);
+
+        // handle without timeout waits
+        let waiting = tokio::spawn(client.request(ThreadsRequestArguments::default()));
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let seq = answer(&mut adapter).await;
+        assert_eq!(waiting.await.unwrap().unwrap().request_seq, seq);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn request_timeout_overrides_client_timeout() {
+        let (client, mut adapter) = connect();
+        let client = client.with_timeout(Duration::from_secs(1));
+        let start = tokio::time::Instant::now();
+        let shorter = client
+            .request(ThreadsRequestArguments::default())
+            .timeout(Duration::from_millis(100))
+            .await;
+        assert!(matches!(shorter, Err(DapError::Timeout(_))));
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+
+        let longer = tokio::spawn(
+            client
+                .request(ThreadsRequestArguments::default())
+                .timeout(Duration::from_secs(10)),
+        );
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        answer(&mut adapter).await;
+        let seq = answer(&mut adapter).await;
+        assert_eq!(longer.await.unwrap().unwrap().request_seq, seq);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reader_timeout_fails_pending_requests() {
+        let (client_io, adapter_io) = tokio::io::duplex(64 * 1024);
+        let (reader, writer) = tokio::io::split(client_io);
+        let reader = AsyncCodec::new(reader).with_idle_timeout(Duration::from_secs(1));
+        let client = Client::spawn_codec(reader, AsyncCodec::new(writer));
+        let pending = client.request(ThreadsRequestArguments::default());
+        assert!(matches!(pending.await, Err(DapError::Timeout(_))));
+        // connection is closed, later requests report why
+        let sent = client.send(OneOf3::This(ThreadsRequestArguments::default().into_req(0)));
+        assert!(matches!(sent, Err(DapError::Timeout(msg)) if msg == "reading from adapter"));
+        drop(adapter_io);
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+
+/// error raised by codecs and everything built on them
+#[derive(Debug)]
+pub enum DapError {
+    /// connection closed before a complete message was read
+    Closed,
+    /// malformed or missing message header
+    Header(String),
+    /// message body is not a valid protocol message, `raw` is the body as received
+    Json {
+        raw: String,
+        source: serde_json::Error,
+    },
+    /// outgoing message could not be serialized
+    Encode(serde_json::Error),
+    /// `Content-Length` exceeds configured limit
+    FrameTooLarge { len: usize, max: usize },
+    /// websocket failure, e.g. handshake error or unknown frame opcode
+    Ws(String),
+    /// peer sent a message which does not fit current conversation
+    Protocol(String),
+    /// peer did not respond in time, e.g. request timeout or idle connection
+    Timeout(String),
+    /// underlying stream error
+    Io(std::io::Error),
+}
+
+pub type DapResult<T> = Result<T, DapError>;
+
+impl DapError {
+    /// return true if error is caused by peer disconnecting
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            DapError::Closed => true,
+            DapError::Io(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+
+    /// return true if peer did not respond in time
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, DapError::Timeout(_))
+    }
+}
+
+impl fmt::Display for DapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DapError::Closed => write!(f, "connection closed in the middle of a message"),
+            DapError::Header(msg) => write!(f, "invalid header: {}", msg),
+            DapError::Json { source, .. } => write!(f, "invalid message: {}", source),
+            DapError::Encode(e) => write!(f, "failed to encode message: {}", e),
+            DapError::FrameTooLarge { len, max } => {
+                write!(f, "message of {} bytes exceeds limit {}", len, max)
+            }
+            DapError::Ws(msg) => write!(f, "websocket error: {}", msg),
+            DapError::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            DapError::Timeout(msg) => write!(f, "{} timed out", msg),
+            DapError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DapError::Json { source, .. } => Some(source),
+            DapError::Encode(e) => Some(e),
+            DapError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for DapError {
+    fn from(e: std::io::Error) -> Self {
+        DapError::Io(e)
+    }
+}
+
+impl From<DapError> for std::io::Error {
+    fn from(e: DapError) -> Self {
+        use std::io::ErrorKind;
+        match e {
+            DapError::Io(e) => e,
+            DapError::Closed => std::io::Error::new(ErrorKind::UnexpectedEof, e),
+            DapError::Timeout(_) => std::io::Error::new(ErrorKind::TimedOut, e),
+            e => std::io::Error::new(ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::*;
+
+    #[test]
+    fn disconnect_and_timeout() {
+        assert!(DapError::Closed.is_disconnect());
+        assert!(DapError::Io(ErrorKind::BrokenPipe.into()).is_disconnect());
+        assert!(!DapError::Io(ErrorKind::PermissionDenied.into()).is_disconnect());
+        assert!(!DapError::Header("x".to_string()).is_disconnect());
+        assert!(DapError::Timeout("read".to_string()).is_timeout());
+        assert!(!DapError::Closed.is_timeout());
+    }
+
+    #[test]
+    fn into_io_error() {
+        let kind = |e: DapError| std::io::Error::from(e).kind();
+        assert_eq!(kind(DapError::Closed), ErrorKind::UnexpectedEof);
+        assert_eq!(
+            kind(DapError::Timeout("read".to_string())),
+            ErrorKind::TimedOut
+        );
+        assert_eq!(
+            kind(DapError::Io(ErrorKind::BrokenPipe.into())),
+            ErrorKind::BrokenPipe
+        );
+        assert_eq!(
+            kind(DapError::FrameTooLarge { len: 2, max: 1 }),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn json_error_keeps_raw_body() {
+        let source = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let e = DapError::Json {
+            raw: "{".to_string(),
+            source,
+        };
+        assert!(e.to_string().starts_with("invalid message: "));
+        assert!(std::error::Error::source(&e).is_some());
+    }
+}
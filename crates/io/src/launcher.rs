@@ -0,0 +1,411 @@
+//! spawn debuggee and report its lifecycle as DAP events
+//!
+//! [`Launcher::spawn`] sends `process` right away, forwards stdout and stderr as `output`
+//! events, sends `exited` as soon as the process ends and `terminated` once its output is
+//! drained. events are handed to `emit` with `seq` 0, the writer is expected to assign
+//! real sequence numbers.
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use dap_ty::{
+    DisconnectArguments, Event, ExitedEventBody, FromEvent, LaunchRequestArguments,
+    OutputEventBody, ProcessEventBody, TerminatedEventBody,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+/// `category` of debuggee output
+pub mod category {
+    pub const STDOUT: &str = "stdout";
+    pub const STDERR: &str = "stderr";
+}
+
+/// implementation specific `launch` attributes understood by [`Launcher`]
+///
+/// can be parsed together with standard attributes as `LaunchRequestArguments<LaunchConfig>`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchConfig {
+    pub program: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// `null` value removes variable from inherited environment
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, Option<String>>,
+}
+
+impl LaunchConfig {
+    /// read config from extension fields of `launch` arguments
+    pub fn from_args(args: &LaunchRequestArguments) -> serde_json::Result<Self> {
+        serde_json::from_value(serde_json::Value::Object(args.extra.clone()))
+    }
+}
+
+/// how long output of an exited debuggee is drained before `terminated` is sent,
+/// pipes may be held open by its children
+pub const OUTPUT_GRACE: Duration = Duration::from_millis(500);
+
+type Emit = Arc<dyn Fn(Event) + Send + Sync>;
+
+/// a running debuggee
+pub struct Launcher {
+    pid: Option<u32>,
+    waiter: JoinHandle<i64>,
+    #[cfg(windows)]
+    job: Option<job::Job>,
+}
+
+/// job object holding debuggee and its children, processes on windows have no groups
+#[cfg(windows)]
+mod job {
+    use std::io;
+    use std::ptr;
+
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, TerminateJobObject,
+    };
+
+    pub struct Job(HANDLE);
+
+    // a job handle may be used from any thread
+    unsafe impl Send for Job {}
+    unsafe impl Sync for Job {}
+
+    impl Job {
+        /// create an anonymous job and put `process` in it, children spawned by
+        /// `process` afterwards join the job too
+        pub fn assign(process: HANDLE) -> io::Result<Self> {
+            let handle = unsafe { CreateJobObjectW(ptr::null(), ptr::null()) };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let job = Self(handle);
+            if unsafe { AssignProcessToJobObject(job.0, process) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(job)
+        }
+
+        pub fn terminate(&self) -> io::Result<()> {
+            if unsafe { TerminateJobObject(self.0, 1) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            unsafe { CloseHandle(self.0) };
+        }
+    }
+}
+
+fn forward<R>(reader: R, category: &'static str, emit: Emit) -> JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(reader);
+        let mut line = vec![];
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line).await {
+                Ok(0) => break,
+                Ok(_) => {
+                    let body = OutputEventBody {
+                        category: Some(category.to_string()),
+                        output: String::from_utf8_lossy(&line).into_owned(),
+                        ..Default::default()
+                    };
+                    emit(body.into_event(0));
+                }
+                Err(e) => {
+                    tracing::warn!("failed to read debuggee {}: {}", category, e);
+                    break;
+                }
+            }
+        }
+    })
+}
+
+impl Launcher {
+    /// spawn `config.program`, must be called inside a tokio runtime
+    ///
+    /// on unix debuggee is put in its own process group so that its children can be
+    /// killed together
+    pub fn spawn<F>(config: &LaunchConfig, emit: F) -> io::Result<Self>
+    where
+        F: Fn(Event) + Send + Sync + 'static,
+    {
+        let emit: Emit = Arc::new(emit);
+        let mut cmd = std::process::Command::new(&config.program);
+        cmd.args(&config.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(cwd) = &config.cwd {
+            cmd.current_dir(cwd);
+        }
+        for (key, value) in &config.env {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            };
+        }
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+        let mut child = Command::from(cmd).kill_on_drop(false).spawn()?;
+        let pid = child.id();
+        #[cfg(windows)]
+        let job = child.raw_handle().and_then(|handle| {
+            job::Job::assign(handle as _)
+                .map_err(|e| tracing::warn!("failed to put debuggee in a job: {}", e))
+                .ok()
+        });
+
+        let process = ProcessEventBody {
+            name: config.program.clone(),
+            system_process_id: pid.map(i64::from),
+            is_local_process: Some(true),
+            start_method: Some("launch".to_string()),
+            ..Default::default()
+        };
+        emit(process.into_event(0));
+
+        let stdout = child
+            .stdout
+            .take()
+            .map(|out| forward(out, category::STDOUT, emit.clone()));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|err| forward(err, category::STDERR, emit.clone()));
+        let waiter = tokio::spawn(async move {
+            let exit_code = match child.wait().await {
+                Ok(status) => status.code().map(i64::from).unwrap_or(-1),
+                Err(e) => {
+                    tracing::warn!("failed to wait debuggee: {}", e);
+                    -1
+                }
+            };
+            emit(
+                ExitedEventBody {
+                    exit_code,
+                    ..Default::default()
+                }
+                .into_event(0),
+            );
+            // children of debuggee may keep pipes open, stop forwarding after a grace period
+            let mut output: Vec<_> = stdout.into_iter().chain(stderr).collect();
+            let drained = async {
+                for task in output.iter_mut() {
+                    task.await.ok();
+                }
+            };
+            if tokio::time::timeout(OUTPUT_GRACE, drained).await.is_err() {
+                tracing::debug!("debuggee output still open after exit, stop forwarding");
+                output.iter().for_each(JoinHandle::abort);
+            }
+            emit(TerminatedEventBody::default().into_event(0));
+            exit_code
+        });
+        Ok(Self {
+            pid,
+            waiter,
+            #[cfg(windows)]
+            job,
+        })
+    }
+
+    /// os process id of debuggee
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    /// kill debuggee and every process in its group, or its job object on windows
+    pub fn kill_tree(&self) -> io::Result<()> {
+        let pid = match self.pid {
+            Some(pid) => pid,
+            None => return Ok(()),
+        };
+        #[cfg(unix)]
+        {
+            // debuggee is leader of its process group
+            if unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) } != 0 {
+                let e = io::Error::last_os_error();
+                // group is already gone
+                if e.raw_os_error() != Some(libc::ESRCH) {
+                    return Err(e);
+                }
+            }
+            Ok(())
+        }
+        #[cfg(windows)]
+        {
+            match &self.job {
+                Some(job) => job.terminate(),
+                None => Err(io::Error::other(format!(
+                    "debuggee {} is not in a job object",
+                    pid
+                ))),
+            }
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("can not kill process tree of {} on this platform", pid),
+            ))
+        }
+    }
+
+    /// handle `disconnect`, kill debuggee unless `terminateDebuggee` is false
+    ///
+    /// the whole tree is killed even if debuggee itself already exited, its children
+    /// may still be running
+    pub fn disconnect(&self, args: Option<&DisconnectArguments>) -> io::Result<()> {
+        let terminate = args.and_then(|a| a.terminate_debuggee).unwrap_or(true);
+        if terminate {
+            self.kill_tree()?;
+        }
+        Ok(())
+    }
+
+    /// wait until debuggee exited and all events are emitted, return exit code
+    pub async fn wait(self) -> io::Result<i64> {
+        self.waiter.await.map_err(io::Error::other)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use super::*;
+
+    type Events = Arc<Mutex<Vec<Event>>>;
+
+    fn spawn_sh(script: &str) -> (Launcher, Events) {
+        let events = Events::default();
+        let config = LaunchConfig {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            ..Default::default()
+        };
+        let sink = events.clone();
+        let launcher =
+            Launcher::spawn(&config, move |event| sink.lock().unwrap().push(event)).unwrap();
+        (launcher, events)
+    }
+
+    fn names(events: &Events) -> Vec<String> {
+        events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.event.clone())
+            .collect()
+    }
+
+    fn output(events: &Events) -> String {
+        events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.event == "output")
+            .filter_map(|e| e.body.as_ref()?["output"].as_str().map(String::from))
+            .collect()
+    }
+
+    fn alive(pid: libc::pid_t) -> bool {
+        unsafe { libc::kill(pid, 0) == 0 }
+    }
+
+    /// wait until `f` holds, fail after a few seconds
+    async fn eventually(mut f: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !f() {
+            assert!(Instant::now() < deadline, "condition not met in time");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn exited_before_terminated() {
+        let (launcher, events) = spawn_sh("echo hello; echo oops >&2; exit 3");
+        assert_eq!(launcher.wait().await.unwrap(), 3);
+        let names = names(&events);
+        assert_eq!(names.first().map(String::as_str), Some("process"));
+        assert_eq!(&names[names.len() - 2..], ["exited", "terminated"]);
+        assert_eq!(names.iter().filter(|n| *n == "output").count(), 2);
+        let exited = events.lock().unwrap()[names.len() - 2]
+            .body
+            .clone()
+            .unwrap();
+        assert_eq!(exited["exitCode"], 3);
+    }
+
+    #[tokio::test]
+    async fn output_held_open_is_drained_within_grace() {
+        // background child inherits stdout and keeps it open after sh exits
+        let (launcher, events) = spawn_sh("sleep 30 & echo $!; echo done");
+        let pgid = launcher.pid().unwrap() as libc::pid_t;
+        let start = Instant::now();
+        assert_eq!(launcher.wait().await.unwrap(), 0);
+        assert!(start.elapsed() < OUTPUT_GRACE + Duration::from_secs(2));
+        assert!(output(&events).ends_with("done\n"));
+        assert_eq!(
+            names(&events).last().map(String::as_str),
+            Some("terminated")
+        );
+        unsafe { libc::killpg(pgid, libc::SIGKILL) };
+    }
+
+    #[tokio::test]
+    async fn disconnect_kills_running_tree() {
+        let (launcher, events) = spawn_sh("sleep 30 & echo $!; wait");
+        eventually(|| output(&events).ends_with('\n')).await;
+        let grandchild: libc::pid_t = output(&events).trim().parse().unwrap();
+        assert!(alive(grandchild));
+        launcher.disconnect(None).unwrap();
+        launcher.wait().await.unwrap();
+        eventually(|| !alive(grandchild)).await;
+    }
+
+    #[tokio::test]
+    async fn disconnect_kills_children_of_exited_debuggee() {
+        let (launcher, events) = spawn_sh("sleep 30 >/dev/null 2>&1 & echo $!");
+        eventually(|| names(&events).iter().any(|n| n == "terminated")).await;
+        let grandchild: libc::pid_t = output(&events).trim().parse().unwrap();
+        assert!(alive(grandchild));
+        launcher.disconnect(None).unwrap();
+        eventually(|| !alive(grandchild)).await;
+        // group is gone, killing it again is fine
+        launcher.disconnect(None).unwrap();
+    }
+
+    #[tokio::test]
+    async fn disconnect_can_keep_debuggee() {
+        let (launcher, _events) = spawn_sh("sleep 30");
+        let pid = launcher.pid().unwrap() as libc::pid_t;
+        let args = DisconnectArguments {
+            terminate_debuggee: Some(false),
+            ..Default::default()
+        };
+        launcher.disconnect(Some(&args)).unwrap();
+        assert!(alive(pid));
+        launcher.kill_tree().unwrap();
+        assert_eq!(launcher.wait().await.unwrap(), -1);
+    }
+}
@@ -3,6 +3,27 @@ mod blocking;
 #[cfg(feature = "async")]
 mod non_blocking;
 
+#[cfg(feature = "async")]
+pub mod cancel;
+#[cfg(feature = "async")]
+pub mod client;
+mod error;
+#[cfg(feature = "async")]
+pub mod launcher;
+pub mod path_map;
+#[cfg(feature = "async")]
+pub mod progress;
+pub mod replay;
+#[cfg(feature = "async")]
+pub mod router;
+#[cfg(feature = "async")]
+pub mod server;
+#[cfg(feature = "tower")]
+pub mod service;
+pub mod session;
+#[cfg(feature = "async")]
+pub mod snapshot;
+pub mod trace;
 mod utils;
 
 const BUF_SIZE: usize = 1024 * 4;
@@ -10,5 +31,32 @@ const BUF_SIZE: usize = 1024 * 4;
 #[cfg(feature = "blocking")]
 pub use blocking::*;
 
+#[cfg(feature = "async")]
+pub use cancel::CancelToken;
+#[cfg(feature = "async")]
+pub use client::{Client, EventStream, PendingRequest};
+#[cfg(feature = "derive")]
+pub use dap_derive::dap_handler;
+pub use error::{DapError, DapResult};
+#[cfg(feature = "async")]
+pub use launcher::{LaunchConfig, Launcher};
 #[cfg(feature = "async")]
 pub use non_blocking::*;
+pub use path_map::{PathMapper, PathMapping};
+#[cfg(feature = "async")]
+pub use progress::ProgressReporter;
+#[cfg(feature = "async")]
+pub use router::{Route, Router};
+#[cfg(feature = "async")]
+pub use server::{serve, serve_with, Concurrency, Ctx, Debuggee, Handler, Policy};
+#[cfg(feature = "tower")]
+pub use service::{CtxRequest, ServiceHandler};
+#[cfg(feature = "async")]
+pub use session::AsyncSessionCodec;
+#[cfg(feature = "blocking")]
+pub use session::SessionCodec;
+pub use session::{Session, SessionError};
+#[cfg(feature = "async")]
+pub use snapshot::{Snapshot, StoppedSnapshot};
+pub use trace::{Direction, TraceRecord, Tracer};
+pub use utils::{Frame, MAX_CONTENT_LENGTH};
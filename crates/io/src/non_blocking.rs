@@ -1,97 +1,221 @@
+use std::time::Duration;
+
 use dap_ty::{Event, OneOf3, Request, Response};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+use crate::trace::{Direction, Tracer};
 use crate::utils::CodecState;
 
-type IOResult<T> = std::io::Result<T>;
+use crate::error::{DapError, DapResult};
 
 /// async protocol message reader/writer
-pub struct AsyncCodec<S: AsyncRead + AsyncWrite> {
+///
+/// reading requires `S: AsyncRead`, writing requires `S: AsyncWrite`, so halves of a
+/// split stream can be used independently
+pub struct AsyncCodec<S> {
     stream: S,
     state: CodecState,
+    read_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
 }
 
-impl<S: AsyncRead + AsyncWrite + Unpin> AsyncCodec<S> {
+impl<S> AsyncCodec<S> {
     pub fn new(stream: S) -> Self {
         Self {
             stream,
             state: CodecState::default(),
+            read_timeout: None,
+            idle_timeout: None,
         }
     }
 
+    /// record every message to `tracer`, overriding `DAP_TRACE` environment variable
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.state.tracer = Some(tracer);
+        self
+    }
+
+    /// reject messages larger than `max` bytes, default is 64 MiB
+    pub fn with_max_content_length(mut self, max: usize) -> Self {
+        self.state.max_content_length = max;
+        self
+    }
+
+    /// fail with [`DapError::Timeout`] if a started message is not complete within `timeout`
+    /// of silence
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// fail with [`DapError::Timeout`] if no message starts within `timeout`
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
     /// get mutable ref of underlying stream
     pub fn stream_mut(&mut self) -> &mut S {
         &mut self.stream
     }
+}
 
-    async fn poll(&mut self) -> tokio::io::Result<usize> {
+impl<S: AsyncRead + Unpin> AsyncCodec<S> {
+    /// read more data, return false on clean eof
+    ///
+    /// `idle` tells whether no part of next message has been read yet
+    async fn poll(&mut self, idle: bool) -> DapResult<bool> {
+        let (timeout, what) = if idle {
+            (self.idle_timeout, "idle connection")
+        } else {
+            (self.read_timeout, "read")
+        };
         let state = &mut self.state;
-        let count = self.stream.read(&mut state.read_buf).await?;
-        if count == 0 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::ConnectionAborted,
-                "read eof",
-            ));
-        }
-        state.read_data.extend_from_slice(&state.read_buf[..count]);
-        Ok(count)
+        let read = self.stream.read(&mut state.read_buf);
+        let count = match timeout {
+            // read is cancel safe, nothing is lost on timeout
+            Some(timeout) => tokio::time::timeout(timeout, read)
+                .await
+                .map_err(|_| DapError::Timeout(what.to_string()))??,
+            None => read.await?,
+        };
+        state.fill(count)
     }
 
-    pub async fn receive(&mut self) -> tokio::io::Result<OneOf3<Request, Response, Event>> {
+    /// read message from peer, return `None` if peer closed connection between messages
+    pub async fn receive(&mut self) -> DapResult<Option<OneOf3<Request, Response, Event>>> {
         loop {
             if let Some(may_ok) = self.state.try_parse_header() {
-                may_ok.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                may_ok?;
                 break;
-            } else {
-                self.poll().await?;
+            } else if !self.poll(self.state.read_data.is_empty()).await? {
+                return Ok(None);
             }
         }
 
         while !self.state.body_ready() {
-            self.poll().await?;
+            self.poll(false).await?;
         }
 
-        self.state
-            .consume_body()
-            .map_err(|e| tokio::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        self.state.consume_body().map(Some)
     }
+}
 
+impl<S: AsyncWrite + Unpin> AsyncCodec<S> {
     /// write message to peer
-    pub async fn send(
-        &mut self,
-        message: OneOf3<Request, Response, Event>,
-    ) -> tokio::io::Result<()> {
-        let json_str = serde_json::to_string(&message)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    pub async fn send(&mut self, message: OneOf3<Request, Response, Event>) -> DapResult<()> {
+        let json_str = serde_json::to_string(&message).map_err(DapError::Encode)?;
         let data = json_str.as_bytes();
+        self.state.trace(Direction::Outbound, data);
         self.stream
             .write_all(format!("Content-Length: {}\r\n\r\n", data.len(),).as_bytes())
             .await?;
         self.stream.write_all(data).await?;
+        self.stream.flush().await?;
         Ok(())
     }
 
     /// helper function to send request only
-    pub async fn send_req(&mut self, message: Request) -> tokio::io::Result<()> {
+    pub async fn send_req(&mut self, message: Request) -> DapResult<()> {
         self.send(OneOf3::This(message)).await
     }
 
     /// helper function to send response only
-    pub async fn send_resp(&mut self, message: Response) -> tokio::io::Result<()> {
+    pub async fn send_resp(&mut self, message: Response) -> DapResult<()> {
         self.send(OneOf3::Among(message)).await
     }
 
     /// helper function to send notification only
-    pub async fn send_event(&mut self, message: Event) -> tokio::io::Result<()> {
+    pub async fn send_event(&mut self, message: Event) -> DapResult<()> {
         self.send(OneOf3::Other(message)).await
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use dap_ty::{FromEvent, TerminatedEventBody};
+    use tokio::io::DuplexStream;
+    use tokio::time::Instant;
+
+    use super::*;
+
+    const LONG: Duration = Duration::from_secs(10);
+    const SHORT: Duration = Duration::from_secs(1);
+
+    fn pipe() -> (AsyncCodec<DuplexStream>, DuplexStream) {
+        let (codec_io, peer) = tokio::io::duplex(1024);
+        (AsyncCodec::new(codec_io), peer)
+    }
+
+    fn framed(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn terminated() -> String {
+        let event = TerminatedEventBody::default().into_event(1);
+        framed(&serde_json::to_string(&event).unwrap())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timeout_between_messages() {
+        let (codec, mut peer) = pipe();
+        let mut codec = codec.with_idle_timeout(SHORT).with_read_timeout(LONG);
+        peer.write_all(terminated().as_bytes()).await.unwrap();
+        assert!(matches!(codec.receive().await, Ok(Some(OneOf3::Other(_)))));
+
+        let start = Instant::now();
+        let idle = codec.receive().await;
+        assert!(matches!(idle, Err(DapError::Timeout(msg)) if msg == "idle connection"));
+        assert_eq!(start.elapsed(), SHORT);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_timeout_inside_message() {
+        let (codec, mut peer) = pipe();
+        let mut codec = codec.with_idle_timeout(SHORT).with_read_timeout(LONG);
+        let mut message = terminated();
+        let rest = message.split_off(message.len() / 2);
+        peer.write_all(message.as_bytes()).await.unwrap();
+        tokio::spawn(async move {
+            // slower than idle timeout, within read timeout
+            tokio::time::sleep(LONG / 2).await;
+            peer.write_all(rest.as_bytes()).await.unwrap();
+            peer.write_all(b"Content-Length: 2\r\n\r\n{").await.unwrap();
+            tokio::time::sleep(LONG * 2).await;
+            drop(peer);
+        });
+        assert!(matches!(codec.receive().await, Ok(Some(OneOf3::Other(_)))));
+
+        let start = Instant::now();
+        let stalled = codec.receive().await;
+        assert!(matches!(stalled, Err(DapError::Timeout(msg)) if msg == "read"));
+        assert_eq!(start.elapsed(), LONG);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_timeout_alone_waits_for_next_message() {
+        let (codec, mut peer) = pipe();
+        let mut codec = codec.with_read_timeout(SHORT);
+        tokio::spawn(async move {
+            tokio::time::sleep(LONG).await;
+            peer.write_all(terminated().as_bytes()).await.unwrap();
+        });
+        assert!(matches!(codec.receive().await, Ok(Some(OneOf3::Other(_)))));
+        // peer dropped between messages
+        assert!(matches!(codec.receive().await, Ok(None)));
+    }
+}
+
 #[cfg(feature = "async_ws")]
 mod ws_codec {
+    use std::future::poll_fn;
+    use std::sync::{Arc, Mutex};
+    use std::task::Poll;
+    use std::time::{Duration, Instant};
 
     use dap_ty::{Event, OneOf3, Request, Response};
     use tokio::net::TcpStream;
+    use tokio::sync::{mpsc, oneshot};
     use ws_tool::{
         codec::{default_handshake_handler, AsyncWsStringCodec},
         frame::OpCode,
@@ -99,74 +223,262 @@ mod ws_codec {
         ClientBuilder, ServerBuilder,
     };
 
-    use super::IOResult;
+    use crate::error::{DapError, DapResult};
+    use crate::trace::{Direction, Tracer};
+
+    type Message = OneOf3<Request, Response, Event>;
+    type Ws = AsyncWsStringCodec<WsAsyncStream<TcpStream>>;
+    type Reply = oneshot::Sender<DapResult<()>>;
+
+    /// ping after `interval` of silence, give up after `timeout` more
+    #[derive(Debug, Clone, Copy)]
+    struct Keepalive {
+        interval: Duration,
+        timeout: Duration,
+    }
+
+    /// frames written by writer task
+    enum Outgoing {
+        Text(String),
+        Ping,
+        Pong(String),
+        Close(u16, String),
+    }
+
+    /// what writer task wakes up for
+    enum Wake {
+        Write(Option<(Outgoing, Option<Reply>)>),
+        Pong(String),
+        Tick,
+    }
+
+    /// reader and writer tasks of a started connection
+    struct Conn {
+        incoming: mpsc::UnboundedReceiver<DapResult<Option<Message>>>,
+        outgoing: mpsc::UnboundedSender<(Outgoing, Option<Reply>)>,
+    }
+
+    impl Conn {
+        /// split `ws` into a reader task and a writer task
+        ///
+        /// reader is never interrupted, so a frame arriving slowly is not lost, pings
+        /// are sent by writer. both tasks stop when codec is dropped
+        fn start(ws: Ws, keepalive: Option<Keepalive>, tracer: Option<Tracer>) -> Self {
+            let (mut rx, mut tx) = ws.split();
+            let (in_tx, incoming) = mpsc::unbounded_channel();
+            let (outgoing, mut out_rx) = mpsc::unbounded_channel();
+            let (pong_tx, mut pong_rx) = mpsc::unbounded_channel();
+            let last_seen = Arc::new(Mutex::new(Instant::now()));
+
+            let reader = {
+                let in_tx = in_tx.clone();
+                let last_seen = last_seen.clone();
+                tokio::spawn(async move {
+                    loop {
+                        let msg = match rx.receive().await {
+                            Ok(msg) => msg,
+                            Err(e) => {
+                                in_tx.send(Err(DapError::Ws(e.to_string()))).ok();
+                                return;
+                            }
+                        };
+                        *last_seen.lock().unwrap() = Instant::now();
+                        let item = match msg.code {
+                            OpCode::Close => Ok(None),
+                            OpCode::Text => {
+                                if let Some(tracer) = &tracer {
+                                    tracer.record(Direction::Inbound, msg.data.as_bytes());
+                                }
+                                serde_json::from_str(&msg.data).map(Some).map_err(|source| {
+                                    DapError::Json {
+                                        raw: msg.data,
+                                        source,
+                                    }
+                                })
+                            }
+                            OpCode::Ping => {
+                                pong_tx.send(msg.data).ok();
+                                continue;
+                            }
+                            OpCode::Pong => continue,
+                            code => Err(DapError::Ws(format!("unknown frame code {:?}", code))),
+                        };
+                        let closed = matches!(item, Ok(None) | Err(DapError::Ws(_)));
+                        if in_tx.send(item).is_err() || closed {
+                            return;
+                        }
+                    }
+                })
+            };
+
+            tokio::spawn(async move {
+                let mut ticks = keepalive.map(|ka| {
+                    let start = tokio::time::Instant::now() + ka.interval;
+                    tokio::time::interval_at(start, ka.interval)
+                });
+                loop {
+                    let wake = poll_fn(|cx| {
+                        if let Poll::Ready(next) = out_rx.poll_recv(cx) {
+                            return Poll::Ready(Wake::Write(next));
+                        }
+                        if let Poll::Ready(Some(data)) = pong_rx.poll_recv(cx) {
+                            return Poll::Ready(Wake::Pong(data));
+                        }
+                        match ticks.as_mut().map(|ticks| ticks.poll_tick(cx)) {
+                            Some(Poll::Ready(_)) => Poll::Ready(Wake::Tick),
+                            _ => Poll::Pending,
+                        }
+                    })
+                    .await;
+                    let (frame, reply) = match (wake, keepalive) {
+                        (Wake::Write(Some(next)), _) => next,
+                        // codec dropped
+                        (Wake::Write(None), _) => break,
+                        (Wake::Pong(data), _) => (Outgoing::Pong(data), None),
+                        (Wake::Tick, Some(ka)) => {
+                            let silence = last_seen.lock().unwrap().elapsed();
+                            if silence >= ka.interval + ka.timeout {
+                                let e = DapError::Timeout("websocket keepalive".to_string());
+                                in_tx.send(Err(e)).ok();
+                                break;
+                            }
+                            if silence < ka.interval {
+                                continue;
+                            }
+                            (Outgoing::Ping, None)
+                        }
+                        (Wake::Tick, None) => continue,
+                    };
+                    let sent = match frame {
+                        Outgoing::Text(data) => tx.send(data).await,
+                        Outgoing::Ping => tx.send((OpCode::Ping, String::new())).await,
+                        Outgoing::Pong(data) => tx.send((OpCode::Pong, data)).await,
+                        Outgoing::Close(status, msg) => tx.send((status, msg)).await,
+                    }
+                    .map_err(|e| DapError::Ws(e.to_string()));
+                    match (sent, reply) {
+                        (sent, Some(reply)) => {
+                            reply.send(sent).ok();
+                        }
+                        (Err(e), None) => tracing::debug!("failed to send control frame: {}", e),
+                        (Ok(()), None) => {}
+                    }
+                }
+                reader.abort();
+            });
+
+            Self { incoming, outgoing }
+        }
+    }
 
     pub struct AsyncWsCodec {
-        ws: AsyncWsStringCodec<WsAsyncStream<TcpStream>>,
+        /// taken when connection is started by first receive or send
+        ws: Option<Ws>,
+        conn: Option<Conn>,
+        tracer: Option<Tracer>,
+        keepalive: Option<Keepalive>,
     }
 
     impl AsyncWsCodec {
-        pub async fn new_client<S: ToString>(addr: S) -> IOResult<Self> {
+        pub async fn new_client<S: ToString>(addr: S) -> DapResult<Self> {
             let ws = ClientBuilder::new(addr)
                 .async_connect(AsyncWsStringCodec::check_fn)
-                .await?;
-            Ok(Self { ws })
+                .await
+                .map_err(|e| DapError::Ws(e.to_string()))?;
+            Ok(Self::with_ws(ws))
         }
 
-        pub async fn new_server(stream: TcpStream) -> IOResult<Self> {
+        pub async fn new_server(stream: TcpStream) -> DapResult<Self> {
             let ws = ServerBuilder::async_accept(
                 stream,
                 default_handshake_handler,
                 AsyncWsStringCodec::factory,
             )
-            .await?;
-            Ok(Self { ws })
-        }
-
-        pub async fn receive(&mut self) -> IOResult<OneOf3<Request, Response, Event>> {
-            let msg = self.ws.receive().await?;
-            if msg.code == OpCode::Close {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::ConnectionAborted,
-                    "peer send close",
-                ));
-            } else if msg.code == OpCode::Text {
-                let msg = serde_json::from_str(&msg.data).map_err(|e| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
-                })?;
-                Ok(msg)
-            } else {
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("unknown frame code {:?}", msg.code),
-                ))
+            .await
+            .map_err(|e| DapError::Ws(e.to_string()))?;
+            Ok(Self::with_ws(ws))
+        }
+
+        fn with_ws(ws: Ws) -> Self {
+            Self {
+                ws: Some(ws),
+                conn: None,
+                tracer: Tracer::from_env(),
+                keepalive: None,
             }
         }
 
-        pub async fn close(&mut self, status: u16, msg: String) -> IOResult<()> {
-            self.ws.send((status, msg)).await?;
-            Ok(())
+        /// record every message to `tracer`, overriding `DAP_TRACE` environment variable
+        pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+            self.tracer = Some(tracer);
+            self
+        }
+
+        /// send ping when no frame arrived for `interval`, fail with [`DapError::Timeout`]
+        /// if peer stays silent for `timeout` after that
+        ///
+        /// pings are sent by a background task, a frame being received is never interrupted
+        pub fn with_keepalive(mut self, interval: Duration, timeout: Duration) -> Self {
+            self.keepalive = Some(Keepalive { interval, timeout });
+            self
+        }
+
+        /// connection tasks, started on first use so builder options apply
+        fn conn(&mut self) -> &mut Conn {
+            let Self {
+                ws,
+                conn,
+                tracer,
+                keepalive,
+            } = self;
+            conn.get_or_insert_with(|| {
+                let ws = ws
+                    .take()
+                    .expect("websocket taken without starting connection");
+                Conn::start(ws, *keepalive, tracer.clone())
+            })
         }
 
-        pub async fn send(&mut self, message: OneOf3<Request, Response, Event>) -> IOResult<()> {
-            let json_str = serde_json::to_string(&message)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-            self.ws.send(json_str).await?;
-            Ok(())
+        async fn write(&mut self, frame: Outgoing) -> DapResult<()> {
+            let (reply, sent) = oneshot::channel();
+            self.conn()
+                .outgoing
+                .send((frame, Some(reply)))
+                .map_err(|_| DapError::Closed)?;
+            sent.await.unwrap_or(Err(DapError::Closed))
+        }
+
+        /// read message from peer, return `None` if peer sent close frame
+        ///
+        /// ping and pong frames are handled in background
+        pub async fn receive(&mut self) -> DapResult<Option<Message>> {
+            self.conn().incoming.recv().await.unwrap_or(Ok(None))
+        }
+
+        pub async fn close(&mut self, status: u16, msg: String) -> DapResult<()> {
+            self.write(Outgoing::Close(status, msg)).await
+        }
+
+        pub async fn send(&mut self, message: Message) -> DapResult<()> {
+            let json_str = serde_json::to_string(&message).map_err(DapError::Encode)?;
+            if let Some(tracer) = &self.tracer {
+                tracer.record(Direction::Outbound, json_str.as_bytes());
+            }
+            self.write(Outgoing::Text(json_str)).await
         }
 
         /// helper function to send request only
-        pub async fn send_req(&mut self, message: Request) -> IOResult<()> {
+        pub async fn send_req(&mut self, message: Request) -> DapResult<()> {
             self.send(OneOf3::This(message)).await
         }
 
         /// helper function to send response only
-        pub async fn send_resp(&mut self, message: Response) -> IOResult<()> {
+        pub async fn send_resp(&mut self, message: Response) -> DapResult<()> {
             self.send(OneOf3::Among(message)).await
         }
 
         /// helper function to send notification only
-        pub async fn send_event(&mut self, message: Event) -> IOResult<()> {
+        pub async fn send_event(&mut self, message: Event) -> DapResult<()> {
             self.send(OneOf3::Other(message)).await
         }
     }
@@ -0,0 +1,334 @@
+//! rewrite `Source.path` between editor (local) and debuggee (remote) file systems
+//!
+//! every `source` object and `sources` array found in request arguments, response body
+//! and event body is rewritten, this covers `Source`, `StackFrame`, `Breakpoint`,
+//! `Scope`, `GotoTarget` and so on without listing each message type.
+use std::str::FromStr;
+
+use dap_ty::{Event, OneOf3, Request, Response};
+use serde_json::Value;
+
+type Message = OneOf3<Request, Response, Event>;
+
+/// a prefix pair, `local` is path seen by editor, `remote` is path seen by adapter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathMapping {
+    pub local: String,
+    pub remote: String,
+}
+
+impl FromStr for PathMapping {
+    type Err = String;
+
+    /// parse `local=remote`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((local, remote)) if !local.is_empty() && !remote.is_empty() => Ok(Self {
+                local: local.to_string(),
+                remote: remote.to_string(),
+            }),
+            _ => Err(format!("invalid path mapping {:?}, expect local=remote", s)),
+        }
+    }
+}
+
+fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix)?;
+    let at_boundary =
+        rest.is_empty() || prefix.ends_with(['/', '\\']) || rest.starts_with(['/', '\\']);
+    at_boundary.then_some(rest)
+}
+
+/// separator used by `prefix`, `None` if it has none
+fn separator(prefix: &str) -> Option<char> {
+    match (prefix.contains('/'), prefix.contains('\\')) {
+        (true, _) => Some('/'),
+        (false, true) => Some('\\'),
+        (false, false) => None,
+    }
+}
+
+/// append `rest` of a path matching prefix `from` to prefix `to`
+///
+/// if prefixes use different separators, e.g. a windows host and a linux container,
+/// separators of `rest` are converted to the style of `to`
+fn join(from: &str, to: &str, rest: &str) -> String {
+    match (separator(from), separator(to)) {
+        (Some(from_sep), Some(to_sep)) if from_sep != to_sep => {
+            let rest: String = rest
+                .chars()
+                .map(|c| if c == from_sep { to_sep } else { c })
+                .collect();
+            format!("{}{}", to, rest)
+        }
+        _ => format!("{}{}", to, rest),
+    }
+}
+
+/// direction a message travels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Adapter,
+    Client,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PathMapper {
+    mappings: Vec<PathMapping>,
+}
+
+impl PathMapper {
+    pub fn new(mappings: Vec<PathMapping>) -> Self {
+        Self { mappings }
+    }
+
+    /// add a `local` <-> `remote` prefix pair
+    pub fn add<L: Into<String>, R: Into<String>>(mut self, local: L, remote: R) -> Self {
+        self.mappings.push(PathMapping {
+            local: local.into(),
+            remote: remote.into(),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    fn map(&self, path: &str, target: Target) -> Option<String> {
+        // longest matching prefix wins
+        self.mappings
+            .iter()
+            .filter_map(|m| {
+                let (from, to) = match target {
+                    Target::Adapter => (&m.local, &m.remote),
+                    Target::Client => (&m.remote, &m.local),
+                };
+                strip_prefix(path, from).map(|rest| (from.len(), join(from, to, rest)))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, mapped)| mapped)
+    }
+
+    /// map a local path to remote path, return `None` if no mapping matches
+    pub fn to_remote(&self, path: &str) -> Option<String> {
+        self.map(path, Target::Adapter)
+    }
+
+    /// map a remote path to local path, return `None` if no mapping matches
+    pub fn to_local(&self, path: &str) -> Option<String> {
+        self.map(path, Target::Client)
+    }
+
+    /// rewrite message sent from editor to adapter
+    pub fn map_to_adapter(&self, msg: &mut Message) {
+        self.map_message(msg, Target::Adapter)
+    }
+
+    /// rewrite message sent from adapter to editor
+    pub fn map_to_client(&self, msg: &mut Message) {
+        self.map_message(msg, Target::Client)
+    }
+
+    /// rewrite json of a whole message sent from editor to adapter, return true if
+    /// any path changed
+    pub fn map_value_to_adapter(&self, msg: &mut Value) -> bool {
+        self.map_value(msg, Target::Adapter)
+    }
+
+    /// rewrite json of a whole message sent from adapter to editor, return true if
+    /// any path changed
+    pub fn map_value_to_client(&self, msg: &mut Value) -> bool {
+        self.map_value(msg, Target::Client)
+    }
+
+    fn map_message(&self, msg: &mut Message, target: Target) {
+        if self.is_empty() {
+            return;
+        }
+        let value = match msg {
+            OneOf3::This(req) => req.arguments.as_mut(),
+            OneOf3::Among(resp) => resp.body.as_mut(),
+            OneOf3::Other(event) => event.body.as_mut(),
+        };
+        if let Some(value) = value {
+            self.map_value(value, target);
+        }
+    }
+
+    fn map_value(&self, value: &mut Value, target: Target) -> bool {
+        let mut changed = false;
+        match value {
+            Value::Object(obj) => {
+                for (key, value) in obj.iter_mut() {
+                    changed |= match (key.as_str(), value) {
+                        ("source", source @ Value::Object(_)) => self.map_source(source, target),
+                        ("sources", Value::Array(sources)) => sources
+                            .iter_mut()
+                            .fold(false, |c, source| self.map_source(source, target) | c),
+                        (_, value) => self.map_value(value, target),
+                    }
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    changed |= self.map_value(value, target);
+                }
+            }
+            _ => {}
+        }
+        changed
+    }
+
+    fn map_source(&self, source: &mut Value, target: Target) -> bool {
+        let mut changed = false;
+        if let Some(Value::String(path)) = source.get_mut("path") {
+            if let Some(mapped) = self.map(path, target) {
+                changed = *path != mapped;
+                *path = mapped;
+            }
+        }
+        if let Some(Value::Array(sources)) = source.get_mut("sources") {
+            for source in sources {
+                changed |= self.map_source(source, target);
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parse_mapping() {
+        let mapping: PathMapping = "/home/me/proj=/app".parse().unwrap();
+        assert_eq!(mapping.local, "/home/me/proj");
+        assert_eq!(mapping.remote, "/app");
+        assert!("/home/me/proj".parse::<PathMapping>().is_err());
+        assert!("=/app".parse::<PathMapping>().is_err());
+        assert!("/home/me=".parse::<PathMapping>().is_err());
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let mapper = PathMapper::default()
+            .add("/home/me", "/root")
+            .add("/home/me/proj", "/app");
+        assert_eq!(
+            mapper.to_remote("/home/me/proj/src/main.rs").as_deref(),
+            Some("/app/src/main.rs")
+        );
+        assert_eq!(
+            mapper.to_remote("/home/me/other.rs").as_deref(),
+            Some("/root/other.rs")
+        );
+        assert_eq!(
+            mapper.to_local("/app/src/main.rs").as_deref(),
+            Some("/home/me/proj/src/main.rs")
+        );
+        assert_eq!(mapper.to_remote("/tmp/x.rs"), None);
+    }
+
+    #[test]
+    fn prefix_matches_at_boundary_only() {
+        let mapper = PathMapper::default().add("/home/me/proj", "/app");
+        assert_eq!(mapper.to_remote("/home/me/project/main.rs"), None);
+        assert_eq!(mapper.to_remote("/home/me/proj").as_deref(), Some("/app"));
+        assert_eq!(
+            mapper.to_remote("/home/me/proj/main.rs").as_deref(),
+            Some("/app/main.rs")
+        );
+
+        let mapper = PathMapper::default().add("/home/me/proj/", "/app/");
+        assert_eq!(
+            mapper.to_remote("/home/me/proj/main.rs").as_deref(),
+            Some("/app/main.rs")
+        );
+    }
+
+    #[test]
+    fn windows_separator_is_boundary() {
+        let mapper = PathMapper::default().add("C:\\proj", "/app");
+        assert_eq!(
+            mapper.to_remote("C:\\proj\\main.rs").as_deref(),
+            Some("/app/main.rs")
+        );
+        assert_eq!(mapper.to_remote("C:\\project\\main.rs"), None);
+    }
+
+    #[test]
+    fn separators_follow_target_prefix() {
+        let mapper = PathMapper::default().add("C:\\proj", "/app");
+        assert_eq!(
+            mapper.to_remote("C:\\proj\\src\\lib\\mod.rs").as_deref(),
+            Some("/app/src/lib/mod.rs")
+        );
+        assert_eq!(
+            mapper.to_local("/app/src/main.rs").as_deref(),
+            Some("C:\\proj\\src\\main.rs")
+        );
+        assert_eq!(mapper.to_local("/app").as_deref(), Some("C:\\proj"));
+        // same style, or a prefix without separator, keeps path as is
+        let mapper = PathMapper::default().add("/home/me", "/app");
+        assert_eq!(
+            mapper.to_remote("/home/me/a\\b").as_deref(),
+            Some("/app/a\\b")
+        );
+        let mapper = PathMapper::default().add("C:", "D:");
+        assert_eq!(mapper.to_remote("C:\\a/b").as_deref(), Some("D:\\a/b"));
+    }
+
+    #[test]
+    fn rewrite_nested_sources() {
+        let mapper = PathMapper::default().add("/home/me/proj", "/app");
+        let mut msg: Message = serde_json::from_value(json!({
+            "seq": 3,
+            "type": "response",
+            "request_seq": 2,
+            "command": "stackTrace",
+            "success": true,
+            "body": {
+                "stackFrames": [{
+                    "id": 1,
+                    "name": "main",
+                    "line": 1,
+                    "column": 1,
+                    "source": {"path": "/app/main.rs", "sources": [{"path": "/app/gen.rs"}]},
+                }],
+                "totalFrames": 1,
+            },
+        }))
+        .unwrap();
+        mapper.map_to_client(&mut msg);
+        let body = match msg {
+            OneOf3::Among(resp) => resp.body.unwrap(),
+            other => panic!("expected response, got {:?}", other),
+        };
+        let source = &body["stackFrames"][0]["source"];
+        assert_eq!(source["path"], "/home/me/proj/main.rs");
+        assert_eq!(source["sources"][0]["path"], "/home/me/proj/gen.rs");
+    }
+
+    #[test]
+    fn value_mapping_reports_change() {
+        let mapper = PathMapper::default().add("/home/me/proj", "/app");
+        let mut msg = json!({
+            "seq": 1,
+            "type": "request",
+            "command": "setBreakpoints",
+            "arguments": {"source": {"path": "/home/me/proj/main.rs"}, "breakpoints": []},
+        });
+        assert!(mapper.map_value_to_adapter(&mut msg));
+        assert_eq!(msg["arguments"]["source"]["path"], "/app/main.rs");
+
+        let mut msg = json!({"seq": 1, "type": "event", "event": "loadedSource", "body": {
+            "reason": "new",
+            "source": {"path": "/usr/lib/libc.so"},
+        }});
+        assert!(!mapper.map_value_to_client(&mut msg));
+    }
+}
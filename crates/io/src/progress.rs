@@ -0,0 +1,242 @@
+//! report progress of long running operations to client
+//!
+//! a [`ProgressReporter`] sends `progressStart` when created, `progressUpdate` at most once
+//! per [`interval`](ProgressReporter::with_interval) and `progressEnd` when dropped. the last
+//! throttled update is sent before `progressEnd`, so client always sees final state. nothing
+//! is sent if client did not announce `supportsProgressReporting`, cancellation still works.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use dap_ty::{ProgressEndEventBody, ProgressStartEventBody, ProgressUpdateEventBody};
+use tokio::time::Instant;
+
+use crate::cancel::CancelToken;
+use crate::server::Ctx;
+
+/// default minimal interval between two `progressUpdate` events
+pub const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// handle of a running progress, created by [`Ctx::progress`]
+pub struct ProgressReporter {
+    ctx: Ctx,
+    id: String,
+    token: CancelToken,
+    /// false if client does not support progress reporting
+    enabled: bool,
+    interval: Duration,
+    last_update: Instant,
+    /// latest update dropped by throttling
+    pending: Option<ProgressUpdateEventBody>,
+    end_message: Option<String>,
+}
+
+impl ProgressReporter {
+    pub(crate) fn start(
+        ctx: Ctx,
+        title: String,
+        request_id: Option<i64>,
+        cancellable: bool,
+    ) -> Self {
+        let id = format!("progress-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        // cancelling either the request or the progress stops the operation
+        let token = match request_id {
+            Some(seq) => {
+                let token = ctx.cancel_token(seq);
+                ctx.share_progress_token(&id, token.clone());
+                token
+            }
+            None => ctx.progress_token(&id),
+        };
+        let mut enabled = ctx.supports_progress_reporting();
+        if enabled {
+            let body = ProgressStartEventBody {
+                progress_id: id.clone(),
+                title,
+                request_id,
+                cancellable: Some(cancellable),
+                ..Default::default()
+            };
+            if let Err(e) = ctx.send_event(body) {
+                tracing::debug!("failed to send progressStart: {}", e);
+                enabled = false;
+            }
+        }
+        Self {
+            ctx,
+            id,
+            token,
+            enabled,
+            interval: UPDATE_INTERVAL,
+            last_update: Instant::now(),
+            pending: None,
+            end_message: None,
+        }
+    }
+
+    /// set minimal interval between two `progressUpdate` events
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// `progressId` of this progress
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// whether events are sent to client
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// wait until client cancelled this progress or the request it reports on
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+
+    /// send `progressUpdate`, `percentage` is clamped to 0..=100
+    ///
+    /// update is held back if previous one was sent less than interval ago, return whether
+    /// it was sent. a held back update is replaced by the next one and sent before
+    /// `progressEnd` if nothing else was sent
+    pub fn update(&mut self, message: Option<String>, percentage: Option<f64>) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let body = ProgressUpdateEventBody {
+            progress_id: self.id.clone(),
+            message,
+            percentage: percentage.map(|p| p.clamp(0.0, 100.0)),
+        };
+        if self.last_update.elapsed() < self.interval {
+            self.pending = Some(body);
+            return false;
+        }
+        self.pending = None;
+        match self.ctx.send_event(body) {
+            Ok(()) => {
+                self.last_update = Instant::now();
+                true
+            }
+            Err(e) => {
+                tracing::debug!("failed to send progressUpdate: {}", e);
+                false
+            }
+        }
+    }
+
+    /// end progress with a final message
+    pub fn finish(mut self, message: impl Into<String>) {
+        self.end_message = Some(message.into());
+    }
+}
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        self.ctx.end_progress(&self.id);
+        if !self.enabled {
+            return;
+        }
+        if let Some(body) = self.pending.take() {
+            if let Err(e) = self.ctx.send_event(body) {
+                tracing::debug!("failed to send progressUpdate: {}", e);
+            }
+        }
+        let body = ProgressEndEventBody {
+            progress_id: self.id.clone(),
+            message: self.end_message.take(),
+        };
+        if let Err(e) = self.ctx.send_event(body) {
+            tracing::debug!("failed to send progressEnd: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dap_ty::{Event, OneOf3};
+    use serde_json::json;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::*;
+    use crate::server::tests::initialized_ctx;
+    use crate::server::Message;
+
+    fn sent(rx: &mut UnboundedReceiver<Message>) -> Vec<Event> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|msg| match msg {
+                OneOf3::Other(event) => Some(event),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn message(event: &Event) -> Option<&str> {
+        event.body.as_ref()?["message"].as_str()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn updates_are_throttled_and_flushed() {
+        let (ctx, mut rx) = initialized_ctx(json!({"supportsProgressReporting": true}));
+        let mut progress = ctx
+            .progress("indexing", None, true)
+            .with_interval(Duration::from_secs(1));
+        assert!(progress.is_enabled());
+        assert!(!progress.update(Some("a".to_string()), Some(10.0)));
+        assert!(!progress.update(Some("b".to_string()), Some(20.0)));
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(progress.update(Some("c".to_string()), Some(150.0)));
+        assert!(!progress.update(Some("d".to_string()), None));
+
+        let events = sent(&mut rx);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event, "progressStart");
+        assert_eq!(events[0].body.as_ref().unwrap()["cancellable"], true);
+        assert_eq!(message(&events[1]), Some("c"));
+        assert_eq!(events[1].body.as_ref().unwrap()["percentage"], 100.0);
+
+        // held back update goes out right before progressEnd
+        progress.finish("done");
+        let events = sent(&mut rx);
+        let names: Vec<_> = events.iter().map(|e| e.event.as_str()).collect();
+        assert_eq!(names, ["progressUpdate", "progressEnd"]);
+        assert_eq!(message(&events[0]), Some("d"));
+        assert_eq!(message(&events[1]), Some("done"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn end_is_sent_on_drop() {
+        let (ctx, mut rx) = initialized_ctx(json!({"supportsProgressReporting": true}));
+        let progress = ctx.progress("loading", None, false);
+        let id = progress.id().to_string();
+        drop(progress);
+        let events = sent(&mut rx);
+        let names: Vec<_> = events.iter().map(|e| e.event.as_str()).collect();
+        assert_eq!(names, ["progressStart", "progressEnd"]);
+        assert_eq!(events[1].body.as_ref().unwrap()["progressId"], id.as_str());
+        assert_eq!(message(&events[1]), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unsupported_client_gets_nothing_but_can_cancel() {
+        let (ctx, mut rx) = initialized_ctx(json!({}));
+        let mut progress = ctx.progress("loading", None, true);
+        assert!(!progress.is_enabled());
+        tokio::time::advance(UPDATE_INTERVAL).await;
+        assert!(!progress.update(None, Some(50.0)));
+        ctx.progress_token(progress.id()).cancel();
+        assert!(progress.is_cancelled());
+        drop(progress);
+        assert!(sent(&mut rx).is_empty());
+    }
+}
@@ -0,0 +1,475 @@
+//! replay a recorded trace as mock adapter or mock client
+//!
+//! trace is split into segments, each segment starts with a message received from peer
+//! (the trigger) and contains every message sent by the replayed side until next trigger.
+//! when a message comes in, the first pending segment whose trigger matches is consumed
+//! and its messages are sent back. by default any pending segment may match, so peers
+//! which send concurrent requests in a different order still replay, use
+//! [`Replay::strict`] to require triggers in recorded order.
+//!
+//! - requests match by `command` and `arguments`
+//! - responses match by `command` and `success`
+//! - events match by `event`
+//!
+//! `seq` is never compared, replayed messages are renumbered and `request_seq` of replayed
+//! responses is rewritten to the `seq` of the matched request.
+use std::collections::HashMap;
+use std::path::Path;
+
+use dap_ty::{Event, OneOf3, Request, Response};
+
+use crate::error::{DapError, DapResult};
+use crate::trace::{read_trace, Direction, TraceRecord};
+
+type Message = OneOf3<Request, Response, Event>;
+
+/// which side of the recorded conversation to act as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// act as debug adapter, editor connects to us
+    Adapter,
+    /// act as editor, we connect to a debug adapter
+    Client,
+}
+
+/// incoming message does not match any recorded message
+#[derive(Debug, Clone)]
+pub struct UnexpectedMessage(pub Box<Message>);
+
+impl std::fmt::Display for UnexpectedMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.as_ref() {
+            OneOf3::This(req) => write!(f, "unexpected request {}", req.command),
+            OneOf3::Among(resp) => write!(f, "unexpected response {}", resp.command),
+            OneOf3::Other(event) => write!(f, "unexpected event {}", event.event),
+        }
+    }
+}
+
+impl std::error::Error for UnexpectedMessage {}
+
+impl From<UnexpectedMessage> for DapError {
+    fn from(e: UnexpectedMessage) -> Self {
+        DapError::Protocol(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Segment {
+    trigger: Option<Message>,
+    replies: Vec<Message>,
+    done: bool,
+}
+
+/// trace replay state machine
+#[derive(Debug, Clone)]
+pub struct Replay {
+    segments: Vec<Segment>,
+    seq: i64,
+    /// recorded request seq -> actual request seq
+    seq_map: HashMap<i64, i64>,
+    lenient: bool,
+    strict: bool,
+}
+
+fn sender_of_first_request(records: &[(Direction, Message)]) -> Option<Direction> {
+    records
+        .iter()
+        .find(|(_, msg)| matches!(msg, OneOf3::This(_)))
+        .map(|(dir, _)| *dir)
+}
+
+fn matches(expected: &Message, actual: &Message) -> bool {
+    match (expected, actual) {
+        (OneOf3::This(e), OneOf3::This(a)) => {
+            let null = serde_json::Value::Null;
+            e.command == a.command
+                && e.arguments.as_ref().unwrap_or(&null) == a.arguments.as_ref().unwrap_or(&null)
+        }
+        (OneOf3::Among(e), OneOf3::Among(a)) => e.command == a.command && e.success == a.success,
+        (OneOf3::Other(e), OneOf3::Other(a)) => e.event == a.event,
+        _ => false,
+    }
+}
+
+impl Replay {
+    /// build replay from parsed records
+    ///
+    /// the side which sends the first request is taken as client, so traces recorded
+    /// by editor and by adapter both work
+    pub fn new(records: Vec<TraceRecord>, role: Role) -> serde_json::Result<Self> {
+        let records = records
+            .into_iter()
+            .map(|r| serde_json::from_str(&r.raw).map(|msg| (r.dir, msg)))
+            .collect::<serde_json::Result<Vec<(Direction, Message)>>>()?;
+        let client_dir = sender_of_first_request(&records).unwrap_or(Direction::Inbound);
+        let ours = match role {
+            Role::Client => client_dir,
+            Role::Adapter if client_dir == Direction::Inbound => Direction::Outbound,
+            Role::Adapter => Direction::Inbound,
+        };
+        let mut segments = vec![Segment {
+            trigger: None,
+            replies: vec![],
+            done: false,
+        }];
+        for (dir, msg) in records {
+            if dir == ours {
+                segments.last_mut().unwrap().replies.push(msg);
+            } else {
+                segments.push(Segment {
+                    trigger: Some(msg),
+                    replies: vec![],
+                    done: false,
+                });
+            }
+        }
+        Ok(Self {
+            segments,
+            seq: 0,
+            seq_map: HashMap::new(),
+            lenient: false,
+            strict: false,
+        })
+    }
+
+    /// load trace file written by [`Tracer`](crate::Tracer)
+    pub fn load<P: AsRef<Path>>(path: P, role: Role) -> DapResult<Self> {
+        let records = read_trace(path)?;
+        Self::new(records, role).map_err(|source| DapError::Json {
+            raw: String::new(),
+            source,
+        })
+    }
+
+    /// answer unmatched requests with error response and ignore unmatched
+    /// responses and events, instead of failing
+    pub fn lenient(mut self) -> Self {
+        self.lenient = true;
+        self
+    }
+
+    /// only match the earliest pending segment, a message arriving out of recorded order
+    /// is unexpected
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// messages to send before anything is received, for client role this
+    /// usually contains `initialize` request
+    pub fn start(&mut self) -> Vec<Message> {
+        let first = &mut self.segments[0];
+        if first.done {
+            return vec![];
+        }
+        first.done = true;
+        let replies = first.replies.clone();
+        self.renumber(replies)
+    }
+
+    /// return true if every recorded message has been replayed
+    pub fn finished(&self) -> bool {
+        self.segments.iter().all(|s| s.done)
+    }
+
+    /// feed a message received from peer, return messages to send back
+    pub fn on_message(&mut self, msg: Message) -> Result<Vec<Message>, UnexpectedMessage> {
+        let strict = self.strict;
+        let found = self
+            .segments
+            .iter_mut()
+            .filter(|s| !s.done && s.trigger.is_some())
+            .take(if strict { 1 } else { usize::MAX })
+            .find(|s| {
+                s.trigger
+                    .as_ref()
+                    .map(|t| matches(t, &msg))
+                    .unwrap_or(false)
+            });
+        match found {
+            Some(segment) => {
+                segment.done = true;
+                if let (Some(OneOf3::This(recorded)), OneOf3::This(actual)) =
+                    (&segment.trigger, &msg)
+                {
+                    self.seq_map.insert(recorded.seq, actual.seq);
+                }
+                let replies = segment.replies.clone();
+                Ok(self.renumber(replies))
+            }
+            None if self.lenient => match msg {
+                OneOf3::This(req) => {
+                    tracing::warn!("request {} not found in trace", req.command);
+                    let mut resp = Response::err::<(), _>(
+                        req.seq,
+                        &req.command,
+                        "request not recorded in trace".to_string(),
+                        None,
+                    );
+                    self.seq += 1;
+                    resp.seq = self.seq;
+                    Ok(vec![OneOf3::Among(resp)])
+                }
+                _ => Ok(vec![]),
+            },
+            None => Err(UnexpectedMessage(Box::new(msg))),
+        }
+    }
+
+    fn renumber(&mut self, messages: Vec<Message>) -> Vec<Message> {
+        messages
+            .into_iter()
+            .map(|mut msg| {
+                self.seq += 1;
+                match &mut msg {
+                    OneOf3::This(req) => req.seq = self.seq,
+                    OneOf3::Among(resp) => {
+                        resp.seq = self.seq;
+                        if let Some(actual) = self.seq_map.get(&resp.request_seq) {
+                            resp.request_seq = *actual;
+                        }
+                    }
+                    OneOf3::Other(event) => event.seq = self.seq,
+                }
+                msg
+            })
+            .collect()
+    }
+
+    /// drive a blocking codec until whole trace is replayed
+    ///
+    /// peer disconnecting before that is [`DapError::Closed`]
+    #[cfg(feature = "blocking")]
+    pub fn run<S: std::io::Read + std::io::Write>(
+        &mut self,
+        codec: &mut crate::Codec<S>,
+    ) -> DapResult<()> {
+        for msg in self.start() {
+            codec.send(msg)?;
+        }
+        while !self.finished() {
+            let msg = codec.receive()?.ok_or(DapError::Closed)?;
+            for reply in self.on_message(msg)? {
+                codec.send(reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// drive an async codec until whole trace is replayed
+    #[cfg(feature = "async")]
+    pub async fn run_async<S>(&mut self, codec: &mut crate::AsyncCodec<S>) -> DapResult<()>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        for msg in self.start() {
+            codec.send(msg).await?;
+        }
+        while !self.finished() {
+            let msg = codec.receive().await?.ok_or(DapError::Closed)?;
+            for reply in self.on_message(msg)? {
+                codec.send(reply).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn record(dir: Direction, msg: serde_json::Value) -> TraceRecord {
+        TraceRecord::new(dir, msg.to_string().as_bytes())
+    }
+
+    fn request(seq: i64, command: &str, arguments: serde_json::Value) -> Message {
+        serde_json::from_value(json!({
+            "seq": seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        }))
+        .unwrap()
+    }
+
+    /// trace recorded by an adapter: initialize, then threads
+    fn adapter_trace() -> Vec<TraceRecord> {
+        vec![
+            record(
+                Direction::Inbound,
+                json!({"seq": 1, "type": "request", "command": "initialize", "arguments": {"adapterID": "mock"}}),
+            ),
+            record(
+                Direction::Outbound,
+                json!({"seq": 1, "type": "response", "request_seq": 1, "command": "initialize", "success": true}),
+            ),
+            record(
+                Direction::Outbound,
+                json!({"seq": 2, "type": "event", "event": "initialized"}),
+            ),
+            record(
+                Direction::Inbound,
+                json!({"seq": 2, "type": "request", "command": "threads"}),
+            ),
+            record(
+                Direction::Outbound,
+                json!({"seq": 3, "type": "response", "request_seq": 2, "command": "threads", "success": true, "body": {"threads": []}}),
+            ),
+        ]
+    }
+
+    #[test]
+    fn adapter_answers_matching_requests() {
+        let mut replay = Replay::new(adapter_trace(), Role::Adapter).unwrap();
+        assert!(replay.start().is_empty());
+        let replies = replay
+            .on_message(request(10, "initialize", json!({"adapterID": "mock"})))
+            .unwrap();
+        assert_eq!(replies.len(), 2);
+        match &replies[0] {
+            OneOf3::Among(resp) => {
+                assert_eq!(resp.seq, 1);
+                assert_eq!(resp.request_seq, 10);
+                assert_eq!(resp.command, "initialize");
+            }
+            other => panic!("expected response, got {:?}", other),
+        }
+        assert!(matches!(&replies[1], OneOf3::Other(e) if e.event == "initialized" && e.seq == 2));
+        assert!(!replay.finished());
+
+        let replies = replay
+            .on_message(request(11, "threads", json!(null)))
+            .unwrap();
+        assert!(matches!(&replies[0], OneOf3::Among(r) if r.request_seq == 11 && r.seq == 3));
+        assert!(replay.finished());
+    }
+
+    #[test]
+    fn missing_arguments_match_null() {
+        let mut replay = Replay::new(adapter_trace(), Role::Adapter).unwrap();
+        let threads: Message =
+            serde_json::from_value(json!({"seq": 5, "type": "request", "command": "threads"}))
+                .unwrap();
+        assert_eq!(replay.on_message(threads).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn segments_match_out_of_order() {
+        let mut replay = Replay::new(adapter_trace(), Role::Adapter).unwrap();
+        replay.start();
+        assert!(replay
+            .on_message(request(1, "threads", json!(null)))
+            .is_ok());
+        assert!(replay
+            .on_message(request(2, "initialize", json!({"adapterID": "mock"})))
+            .is_ok());
+        assert!(replay.finished());
+    }
+
+    #[test]
+    fn strict_requires_recorded_order() {
+        let mut replay = Replay::new(adapter_trace(), Role::Adapter)
+            .unwrap()
+            .strict();
+        replay.start();
+        let err = replay
+            .on_message(request(1, "threads", json!(null)))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "unexpected request threads");
+        assert!(replay
+            .on_message(request(2, "initialize", json!({"adapterID": "mock"})))
+            .is_ok());
+        assert!(replay
+            .on_message(request(3, "threads", json!(null)))
+            .is_ok());
+        assert!(replay.finished());
+    }
+
+    #[test]
+    fn strict_and_lenient_answers_out_of_order_request() {
+        let mut replay = Replay::new(adapter_trace(), Role::Adapter)
+            .unwrap()
+            .strict()
+            .lenient();
+        let replies = replay
+            .on_message(request(1, "threads", json!(null)))
+            .unwrap();
+        assert!(matches!(&replies[..], [OneOf3::Among(resp)] if !resp.success));
+        assert!(!replay.finished());
+    }
+
+    #[test]
+    fn segment_is_consumed_once() {
+        let mut replay = Replay::new(adapter_trace(), Role::Adapter).unwrap();
+        replay
+            .on_message(request(1, "threads", json!(null)))
+            .unwrap();
+        let err = replay
+            .on_message(request(2, "threads", json!(null)))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "unexpected request threads");
+    }
+
+    #[test]
+    fn different_arguments_are_unexpected() {
+        let mut replay = Replay::new(adapter_trace(), Role::Adapter).unwrap();
+        assert!(replay
+            .on_message(request(1, "initialize", json!({"adapterID": "other"})))
+            .is_err());
+    }
+
+    #[test]
+    fn lenient_answers_unknown_requests() {
+        let mut replay = Replay::new(adapter_trace(), Role::Adapter)
+            .unwrap()
+            .lenient();
+        let replies = replay.on_message(request(7, "pause", json!({}))).unwrap();
+        match &replies[..] {
+            [OneOf3::Among(resp)] => {
+                assert!(!resp.success);
+                assert_eq!(resp.request_seq, 7);
+                assert_eq!(resp.command, "pause");
+            }
+            other => panic!("expected one error response, got {:?}", other),
+        }
+        let event: Message =
+            serde_json::from_value(json!({"seq": 8, "type": "event", "event": "output"})).unwrap();
+        assert!(replay.on_message(event).unwrap().is_empty());
+    }
+
+    #[test]
+    fn client_starts_with_first_request() {
+        let mut replay = Replay::new(adapter_trace(), Role::Client).unwrap();
+        let start = replay.start();
+        assert!(
+            matches!(&start[..], [OneOf3::This(req)] if req.command == "initialize" && req.seq == 1)
+        );
+        assert!(replay.start().is_empty());
+
+        let response: Message = serde_json::from_value(json!({
+            "seq": 1, "type": "response", "request_seq": 1, "command": "initialize", "success": true,
+        }))
+        .unwrap();
+        assert!(replay.on_message(response).unwrap().is_empty());
+        let event: Message =
+            serde_json::from_value(json!({"seq": 2, "type": "event", "event": "initialized"}))
+                .unwrap();
+        let replies = replay.on_message(event).unwrap();
+        assert!(matches!(&replies[..], [OneOf3::This(req)] if req.command == "threads"));
+    }
+
+    #[test]
+    fn failed_response_does_not_match_success() {
+        let mut replay = Replay::new(adapter_trace(), Role::Client).unwrap();
+        replay.start();
+        let response: Message = serde_json::from_value(json!({
+            "seq": 1, "type": "response", "request_seq": 1, "command": "initialize", "success": false,
+        }))
+        .unwrap();
+        assert!(replay.on_message(response).is_err());
+    }
+}
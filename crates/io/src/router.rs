@@ -0,0 +1,98 @@
+//! [`Handler`] dispatching requests to typed routes by command
+//!
+//! a [`Route`] handles one request type, its command is `FromReq::COMMAND` of
+//! [`Route::Args`]. routes are usually written as async fns with `#[dap_handler]` of
+//! `derive` feature, requests without a route go to [`Router::fallback`].
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use dap_ty::{FromReq, OneOf, Request, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::server::{BoxFuture, Ctx, Handler};
+
+/// handler of a single request type
+pub trait Route: Send + Sync + 'static {
+    type Args: FromReq<Ret = Self::Output> + DeserializeOwned + Send + 'static;
+    /// response body, always `FromReq::Ret` of [`Route::Args`]
+    type Output: Serialize + Send + 'static;
+
+    /// handle parsed arguments, error is sent to client as error message
+    fn call(&self, ctx: Ctx, args: Self::Args) -> BoxFuture<Result<Self::Output, String>>;
+}
+
+type Erased = Box<dyn Fn(Ctx, Request) -> BoxFuture<Response> + Send + Sync>;
+
+fn erase<R: Route>(route: R) -> Erased {
+    let route = Arc::new(route);
+    Box::new(move |ctx, req| {
+        let route = route.clone();
+        Box::pin(async move {
+            let (seq, command) = (req.seq, req.command.clone());
+            let args = match R::Args::from_req(req) {
+                OneOf::This(Ok((_, args))) => args,
+                OneOf::This(Err(e)) => {
+                    let message = format!("invalid {} arguments: {}", command, e);
+                    return Response::err::<(), _>(seq, &command, message, None);
+                }
+                OneOf::Other(req) => unreachable!("{} routed to {}", req.command, R::Args::COMMAND),
+            };
+            match route.call(ctx, args).await {
+                Ok(body) => Response::ok_with::<R::Output, _>(seq, &command, Some(body)),
+                Err(message) => Response::err::<(), _>(seq, &command, message, None),
+            }
+        })
+    })
+}
+
+/// [`Handler`] made of routes keyed by command
+#[derive(Default)]
+pub struct Router {
+    routes: HashMap<&'static str, Erased>,
+    fallback: Option<Box<dyn Handler>>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add a route for `FromReq::COMMAND` of its arguments
+    ///
+    /// # Panics
+    ///
+    /// if command already has a route
+    pub fn handler<R: Route>(mut self, route: R) -> Self {
+        let command = R::Args::COMMAND;
+        if self.routes.insert(command, erase(route)).is_some() {
+            panic!("duplicated route of {}", command);
+        }
+        self
+    }
+
+    /// handle requests without a route, they are answered with an error by default
+    pub fn fallback<H: Handler>(mut self, handler: H) -> Self {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// commands with a route
+    pub fn commands(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.routes.keys().copied()
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, ctx: Ctx, req: Request) -> BoxFuture<Response> {
+        match (self.routes.get(req.command.as_str()), &self.fallback) {
+            (Some(route), _) => route(ctx, req),
+            (None, Some(fallback)) => fallback.handle(ctx, req),
+            (None, None) => {
+                let message = format!("{} is not supported", req.command);
+                let resp = Response::err::<(), _>(req.seq, &req.command, message, None);
+                Box::pin(async move { resp })
+            }
+        }
+    }
+}
@@ -0,0 +1,930 @@
+//! async adapter runtime with adapter-to-client reverse requests
+//!
+//! [`serve`] reads client messages, checks them against [`Session`] lifecycle and hands
+//! requests to a [`Handler`]. handlers get a [`Ctx`] to send events and reverse requests,
+//! e.g. [`Ctx::run_in_terminal`], whose responses are routed back while handler is waiting.
+//!
+//! requests are scheduled by a [`Policy`]: [`serve`] runs other requests one at a time,
+//! handles inspection requests such as `evaluate` concurrently, and `pause`, `disconnect`
+//! and `terminate` as soon as they arrive, see [`serve_with`] to customize it. every event and response goes through one
+//! writer task, so frames are never interleaved.
+//!
+//! `cancel` requests are answered by the runtime itself, handlers observe them through
+//! [`Ctx::cancel_token`] and [`Ctx::progress_token`]. a request cancelled before or while
+//! it is handled is answered with a [`CANCELLED`](crate::cancel::CANCELLED) error response.
+//!
+//! every response and event sent is counted by [`Ctx::resumes`] if it resumes debuggee,
+//! handle tables of dap-adapter bound to it are invalidated without further bookkeeping.
+//!
+//! a panic in a handler only fails its request, it is logged and answered with an error
+//! response whose message is shown to user.
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use dap_adapter::Resumes;
+use dap_ty::{
+    CancelArguments, CompletionsArguments, DataBreakpointInfoArguments, DisconnectArguments,
+    ErrorResponseBody, EvaluateArguments, Event, ExceptionInfoArguments, FromEvent, FromReq,
+    GotoTargetsArguments, InitializeRequestArguments, LoadedSourcesArguments,
+    Message as ErrorMessage, ModulesArguments, OneOf3, PauseArguments, ProcessEventBody, Request,
+    Response, RunInTerminalRequestArguments, RunInTerminalResponseBody, ScopesArguments,
+    SourceArguments, StackTraceArguments, StepInTargetsArguments, TerminateArguments,
+    ThreadsRequestArguments, VariablesArguments,
+};
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot, RwLock};
+
+use crate::cancel::{cancelled_response, CancelToken};
+use crate::error::{DapError, DapResult};
+use crate::launcher::{LaunchConfig, Launcher};
+use crate::progress::ProgressReporter;
+use crate::session::Session;
+use crate::AsyncCodec;
+
+pub(crate) type Message = OneOf3<Request, Response, Event>;
+
+/// `kind` of `runInTerminal` request
+pub mod terminal_kind {
+    pub const INTEGRATED: &str = "integrated";
+    pub const EXTERNAL: &str = "external";
+}
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// parse body of a successful response, error response is returned as
+/// [`DapError::Protocol`]
+pub(crate) fn response_body<R: DeserializeOwned>(resp: Response) -> DapResult<R> {
+    if !resp.success {
+        return Err(DapError::Protocol(format!(
+            "{} failed: {}",
+            resp.command,
+            resp.message.unwrap_or_default()
+        )));
+    }
+    let body = resp.body.unwrap_or(serde_json::Value::Null);
+    R::deserialize(&body).map_err(|source| DapError::Json {
+        raw: body.to_string(),
+        source,
+    })
+}
+
+/// `id` of error message sent when a handler panicked
+pub const HANDLER_PANICKED: i64 = 1;
+
+pub(crate) fn panic_reason(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(reason) => *reason,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(reason) => reason.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+/// error response to a request whose handler panicked, with `showUser` set
+pub(crate) fn panic_response(seq: i64, command: &str, reason: String) -> Response {
+    let error = ErrorMessage {
+        format: "internal error in {command}: {reason}".to_string(),
+        id: HANDLER_PANICKED,
+        send_telemetry: None,
+        show_user: Some(true),
+        url: None,
+        url_label: None,
+        variables: Some(
+            [
+                ("command".to_string(), command.to_string()),
+                ("reason".to_string(), reason.clone()),
+            ]
+            .into_iter()
+            .collect(),
+        ),
+    };
+    Response::err(
+        seq,
+        command,
+        format!("internal error in {}: {}", command, reason),
+        ErrorResponseBody { error: Some(error) },
+    )
+}
+
+/// request handler used by [`serve`]
+///
+/// implemented for `Fn(Ctx, Request) -> impl Future<Output = Response>`
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, ctx: Ctx, req: Request) -> BoxFuture<Response>;
+}
+
+impl<F, Fut> Handler for F
+where
+    F: Fn(Ctx, Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    fn handle(&self, ctx: Ctx, req: Request) -> BoxFuture<Response> {
+        Box::pin(self(ctx, req))
+    }
+}
+
+/// how requests of a command are scheduled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Concurrency {
+    /// run alone, after every earlier request is done
+    Exclusive,
+    /// run alongside other shared requests
+    Shared,
+    /// run as soon as received, regardless of other requests
+    Immediate,
+}
+
+/// commands handled concurrently by default [`Policy`]
+pub const SHARED_COMMANDS: &[&str] = &[
+    CompletionsArguments::COMMAND,
+    DataBreakpointInfoArguments::COMMAND,
+    EvaluateArguments::COMMAND,
+    ExceptionInfoArguments::COMMAND,
+    GotoTargetsArguments::COMMAND,
+    LoadedSourcesArguments::COMMAND,
+    ModulesArguments::COMMAND,
+    ScopesArguments::COMMAND,
+    SourceArguments::COMMAND,
+    StackTraceArguments::COMMAND,
+    StepInTargetsArguments::COMMAND,
+    ThreadsRequestArguments::COMMAND,
+    VariablesArguments::COMMAND,
+];
+
+/// commands handled as soon as received by default [`Policy`]
+pub const IMMEDIATE_COMMANDS: &[&str] = &[
+    PauseArguments::COMMAND,
+    DisconnectArguments::COMMAND,
+    TerminateArguments::COMMAND,
+];
+
+/// per-command [`Concurrency`] of [`serve_with`]
+///
+/// default policy makes [`SHARED_COMMANDS`] shared, [`IMMEDIATE_COMMANDS`] immediate and
+/// everything else exclusive
+#[derive(Debug, Clone)]
+pub struct Policy {
+    default: Concurrency,
+    commands: HashMap<String, Concurrency>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        let shared = SHARED_COMMANDS.iter().map(|c| (*c, Concurrency::Shared));
+        let immediate = IMMEDIATE_COMMANDS
+            .iter()
+            .map(|c| (*c, Concurrency::Immediate));
+        Self {
+            default: Concurrency::Exclusive,
+            commands: shared
+                .chain(immediate)
+                .map(|(c, p)| (c.to_string(), p))
+                .collect(),
+        }
+    }
+}
+
+impl Policy {
+    /// handle every request alone and in order
+    pub fn sequential() -> Self {
+        Self {
+            default: Concurrency::Exclusive,
+            commands: HashMap::new(),
+        }
+    }
+
+    /// set concurrency of `command`
+    pub fn with(mut self, command: &str, concurrency: Concurrency) -> Self {
+        self.commands.insert(command.to_string(), concurrency);
+        self
+    }
+
+    /// set concurrency of commands without explicit setting
+    pub fn with_default(mut self, concurrency: Concurrency) -> Self {
+        self.default = concurrency;
+        self
+    }
+
+    pub fn concurrency(&self, command: &str) -> Concurrency {
+        self.commands.get(command).copied().unwrap_or(self.default)
+    }
+}
+
+struct Outbox {
+    seq: i64,
+    session: Session,
+    /// `None` after connection closed
+    tx: Option<mpsc::UnboundedSender<Message>>,
+}
+
+struct Inner {
+    outbox: Mutex<Outbox>,
+    /// reverse requests waiting for response, keyed by request seq
+    pending: Mutex<HashMap<i64, oneshot::Sender<Response>>>,
+    /// requests received but not answered yet, keyed by request seq
+    requests: Mutex<HashMap<i64, CancelToken>>,
+    /// running progress, keyed by progress id
+    progress: Mutex<HashMap<String, CancelToken>>,
+    resumes: Resumes,
+}
+
+/// handle to the running connection, cheap to clone
+#[derive(Clone)]
+pub struct Ctx {
+    inner: Arc<Inner>,
+}
+
+/// how debuggee was started by [`Ctx::launch`]
+pub enum Debuggee {
+    /// started by client in its terminal
+    Terminal(RunInTerminalResponseBody),
+    /// started by adapter
+    Local(Launcher),
+}
+
+impl Ctx {
+    pub(crate) fn new(tx: mpsc::UnboundedSender<Message>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                outbox: Mutex::new(Outbox {
+                    seq: 0,
+                    session: Session::new(),
+                    tx: Some(tx),
+                }),
+                pending: Mutex::new(HashMap::new()),
+                requests: Mutex::new(HashMap::new()),
+                progress: Mutex::new(HashMap::new()),
+                resumes: Resumes::new(),
+            }),
+        }
+    }
+
+    /// send a message, `seq` is assigned here
+    ///
+    /// `request` is called with assigned seq before message is written
+    fn send_with(&self, msg: Message, request: impl FnOnce(i64)) -> DapResult<()> {
+        let mut outbox = self.inner.outbox.lock().unwrap();
+        let ready = outbox.session.send(msg)?;
+        let mut request = Some(request);
+        for mut msg in ready {
+            outbox.seq += 1;
+            let seq = outbox.seq;
+            match &mut msg {
+                OneOf3::This(req) => {
+                    req.seq = seq;
+                    if let Some(f) = request.take() {
+                        f(seq)
+                    }
+                }
+                OneOf3::Among(resp) => resp.seq = seq,
+                OneOf3::Other(event) => event.seq = seq,
+            }
+            self.inner.resumes.observe(&msg);
+            outbox
+                .tx
+                .as_ref()
+                .ok_or(DapError::Closed)?
+                .send(msg)
+                .map_err(|_| DapError::Closed)?;
+        }
+        Ok(())
+    }
+
+    /// send a message to client, `seq` is always overwritten
+    pub fn send(&self, msg: Message) -> DapResult<()> {
+        self.send_with(msg, |_| {})
+    }
+
+    /// send an event to client
+    pub fn send_event<T: FromEvent>(&self, body: T) -> DapResult<()> {
+        self.send(OneOf3::Other(body.into_event(0)))
+    }
+
+    /// event callback for [`Launcher::spawn`], send errors are logged
+    pub fn event_sink(&self) -> impl Fn(Event) + Send + Sync + 'static {
+        let ctx = self.clone();
+        move |event| {
+            if let Err(e) = ctx.send(OneOf3::Other(event)) {
+                tracing::warn!("failed to send event: {}", e);
+            }
+        }
+    }
+
+    /// send a reverse request and wait for client's response
+    pub async fn request<T: FromReq>(&self, args: T) -> DapResult<Response> {
+        let (tx, rx) = oneshot::channel();
+        let pending = &self.inner.pending;
+        self.send_with(OneOf3::This(args.into_req(0)), |seq| {
+            pending.lock().unwrap().insert(seq, tx);
+        })?;
+        rx.await.map_err(|_| DapError::Closed)
+    }
+
+    /// send a reverse request and parse response body, error response is returned as
+    /// [`DapError::Protocol`]
+    pub async fn call<T: FromReq>(&self, args: T) -> DapResult<T::Ret>
+    where
+        T::Ret: DeserializeOwned,
+    {
+        response_body(self.request(args).await?)
+    }
+
+    /// resume counter of this connection, bumped for every successful response of a
+    /// continue or step command and every `continued` event sent
+    ///
+    /// create variable and frame tables with `HandleTable::tracking(ctx.resumes())` to
+    /// invalidate them on resume
+    pub fn resumes(&self) -> Resumes {
+        self.inner.resumes.clone()
+    }
+
+    /// arguments of `initialize` request
+    pub fn client(&self) -> Option<InitializeRequestArguments> {
+        self.inner.outbox.lock().unwrap().session.client().cloned()
+    }
+
+    /// whether client announced `supportsRunInTerminalRequest`
+    pub fn supports_run_in_terminal(&self) -> bool {
+        self.client()
+            .and_then(|c| c.supports_run_in_terminal_request)
+            .unwrap_or(false)
+    }
+
+    /// whether client announced `supportsProgressReporting`
+    pub fn supports_progress_reporting(&self) -> bool {
+        self.client()
+            .and_then(|c| c.supports_progress_reporting)
+            .unwrap_or(false)
+    }
+
+    /// ask client to run a command in its terminal
+    pub async fn run_in_terminal(
+        &self,
+        args: RunInTerminalRequestArguments,
+    ) -> DapResult<RunInTerminalResponseBody> {
+        self.call(args).await
+    }
+
+    /// start debuggee in client's integrated terminal if supported, otherwise spawn it
+    /// with [`Launcher`]
+    pub async fn launch(&self, config: &LaunchConfig) -> DapResult<Debuggee> {
+        if !self.supports_run_in_terminal() {
+            return Ok(Debuggee::Local(Launcher::spawn(config, self.event_sink())?));
+        }
+        let cwd = match &config.cwd {
+            Some(cwd) => cwd.clone(),
+            None => std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+        };
+        let args = RunInTerminalRequestArguments {
+            args: std::iter::once(config.program.clone())
+                .chain(config.args.iter().cloned())
+                .collect(),
+            cwd: cwd.to_string_lossy().into_owned(),
+            env: (!config.env.is_empty()).then(|| config.env.clone().into_iter().collect()),
+            kind: Some(terminal_kind::INTEGRATED.to_string()),
+            title: Some(config.program.clone()),
+        };
+        let body = self.run_in_terminal(args).await?;
+        self.send_event(ProcessEventBody {
+            name: config.program.clone(),
+            system_process_id: body.process_id.map(|pid| pid as i64),
+            is_local_process: Some(true),
+            start_method: Some("launch".to_string()),
+            ..Default::default()
+        })?;
+        Ok(Debuggee::Terminal(body))
+    }
+
+    /// cancellation token of request `seq`, never cancelled if request is not running
+    pub fn cancel_token(&self, seq: i64) -> CancelToken {
+        self.inner
+            .requests
+            .lock()
+            .unwrap()
+            .get(&seq)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// register progress `id` and return its cancellation token, call
+    /// [`end_progress`](Self::end_progress) when progress is done
+    pub fn progress_token(&self, id: &str) -> CancelToken {
+        self.inner
+            .progress
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// report progress of a long running operation, see [`ProgressReporter`]
+    ///
+    /// progress is cancelled together with request `request_id` if given, `cancellable`
+    /// tells client whether to offer a cancel button
+    pub fn progress(
+        &self,
+        title: impl Into<String>,
+        request_id: Option<i64>,
+        cancellable: bool,
+    ) -> ProgressReporter {
+        ProgressReporter::start(self.clone(), title.into(), request_id, cancellable)
+    }
+
+    /// register progress `id` with an existing token
+    pub(crate) fn share_progress_token(&self, id: &str, token: CancelToken) {
+        self.inner
+            .progress
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), token);
+    }
+
+    /// forget progress `id`
+    pub fn end_progress(&self, id: &str) {
+        self.inner.progress.lock().unwrap().remove(id);
+    }
+
+    /// handle `cancel` request
+    fn cancel(&self, args: &CancelArguments) {
+        if let Some(seq) = args.request_id {
+            if let Some(token) = self.inner.requests.lock().unwrap().get(&seq) {
+                token.cancel();
+            }
+        }
+        if let Some(id) = &args.progress_id {
+            if let Some(token) = self.inner.progress.lock().unwrap().get(id) {
+                token.cancel();
+            }
+        }
+    }
+
+    /// run `handler` on a request, answer it with `cancelled` if it was cancelled
+    ///
+    /// a panicking handler is answered with an error shown to user
+    async fn dispatch<H: Handler>(&self, handler: &H, req: Request) {
+        let token = self.cancel_token(req.seq);
+        let resp = if token.is_cancelled() {
+            cancelled_response(&req)
+        } else {
+            // run in its own task so a panic only fails this request
+            let resp = match tokio::spawn(handler.handle(self.clone(), req.clone())).await {
+                Ok(resp) => resp,
+                Err(e) if e.is_panic() => {
+                    let reason = panic_reason(e.into_panic());
+                    tracing::error!(
+                        "handler of {} {} panicked: {}",
+                        req.seq,
+                        req.command,
+                        reason
+                    );
+                    panic_response(req.seq, &req.command, reason)
+                }
+                Err(e) => Response::err::<(), _>(req.seq, &req.command, e.to_string(), None),
+            };
+            if token.is_cancelled() {
+                cancelled_response(&req)
+            } else {
+                resp
+            }
+        };
+        self.inner.requests.lock().unwrap().remove(&req.seq);
+        if let Err(e) = self.send(OneOf3::Among(resp)) {
+            tracing::warn!("failed to send response: {}", e);
+        }
+    }
+
+    fn route_response(&self, resp: Response) {
+        match self.inner.pending.lock().unwrap().remove(&resp.request_seq) {
+            Some(tx) => {
+                tx.send(resp).ok();
+            }
+            None => tracing::warn!(
+                "response to unknown request {} {}",
+                resp.request_seq,
+                resp.command
+            ),
+        }
+    }
+
+    fn receive(&self, msg: Message) -> Vec<Message> {
+        let checked = self.inner.outbox.lock().unwrap().session.receive(msg);
+        match checked {
+            Ok(ready) => ready,
+            Err(e) => {
+                tracing::warn!("{}", e);
+                if let Some(resp) = e.error_response() {
+                    self.send(OneOf3::Among(resp)).ok();
+                }
+                vec![]
+            }
+        }
+    }
+
+    /// fail pending reverse requests with [`DapError::Closed`]
+    fn drop_pending(&self) {
+        self.inner.pending.lock().unwrap().clear();
+    }
+
+    /// stop writing, later sends fail with [`DapError::Closed`]
+    fn close(&self) {
+        self.inner.outbox.lock().unwrap().tx = None;
+    }
+}
+
+/// run adapter on a connection until client closes it, with default [`Policy`]
+///
+/// must be called inside a tokio runtime
+pub async fn serve<R, W, H>(reader: R, writer: W, handler: H) -> DapResult<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+    H: Handler,
+{
+    serve_with(reader, writer, handler, Policy::default()).await
+}
+
+/// run adapter on a connection until client closes it, scheduling requests by `policy`
+///
+/// exclusive and shared requests start in the order they are received, immediate ones
+/// skip the queue. must be called inside a tokio runtime
+pub async fn serve_with<R, W, H>(reader: R, writer: W, handler: H, policy: Policy) -> DapResult<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+    H: Handler,
+{
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+    let ctx = Ctx::new(out_tx);
+    let writer = tokio::spawn(async move {
+        let mut codec = AsyncCodec::new(writer);
+        while let Some(msg) = out_rx.recv().await {
+            codec.send(msg).await?;
+        }
+        codec.stream_mut().shutdown().await?;
+        DapResult::Ok(())
+    });
+
+    let handler = Arc::new(handler);
+    // every handler task holds a clone, channel closes when all of them are done
+    let (running, mut all_done) = mpsc::channel::<()>(1);
+    let spawn = {
+        let ctx = ctx.clone();
+        let handler = handler.clone();
+        let running = running.clone();
+        move |req: Request, guard: Option<Box<dyn Send>>| {
+            let ctx = ctx.clone();
+            let handler = handler.clone();
+            let running = running.clone();
+            tokio::spawn(async move {
+                ctx.dispatch(handler.as_ref(), req).await;
+                drop(guard);
+                drop(running);
+            });
+        }
+    };
+
+    let (req_tx, mut req_rx) = mpsc::unbounded_channel::<(Request, Concurrency)>();
+    let scheduler = {
+        let spawn = spawn.clone();
+        tokio::spawn(async move {
+            let lock = Arc::new(RwLock::new(()));
+            while let Some((req, concurrency)) = req_rx.recv().await {
+                // wait here so later requests can not overtake this one
+                let guard: Box<dyn Send> = match concurrency {
+                    Concurrency::Exclusive => Box::new(lock.clone().write_owned().await),
+                    _ => Box::new(lock.clone().read_owned().await),
+                };
+                spawn(req, Some(guard));
+            }
+        })
+    };
+
+    let mut codec = AsyncCodec::new(reader);
+    let read = loop {
+        let msg = match codec.receive().await {
+            Ok(Some(msg)) => msg,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        };
+        for msg in ctx.receive(msg) {
+            match msg {
+                OneOf3::This(req) if CancelArguments::can_cast(&req) => {
+                    let args = req
+                        .arguments
+                        .clone()
+                        .map(serde_json::from_value::<CancelArguments>)
+                        .transpose();
+                    let resp = match args {
+                        Ok(args) => {
+                            ctx.cancel(&args.unwrap_or_default());
+                            Response::ok_with::<(), _>(req.seq, &req.command, None)
+                        }
+                        Err(e) => {
+                            Response::err::<(), _>(req.seq, &req.command, e.to_string(), None)
+                        }
+                    };
+                    ctx.send(OneOf3::Among(resp)).ok();
+                }
+                OneOf3::This(req) => {
+                    ctx.inner
+                        .requests
+                        .lock()
+                        .unwrap()
+                        .insert(req.seq, CancelToken::new());
+                    match policy.concurrency(&req.command) {
+                        Concurrency::Immediate => spawn(req, None),
+                        concurrency => {
+                            req_tx.send((req, concurrency)).ok();
+                        }
+                    }
+                }
+                OneOf3::Among(resp) => ctx.route_response(resp),
+                OneOf3::Other(event) => tracing::debug!("ignore client event {}", event.event),
+            }
+        }
+    };
+
+    // no response can arrive anymore, unblock handlers waiting for one
+    ctx.drop_pending();
+    drop(req_tx);
+    scheduler.await.ok();
+    drop(spawn);
+    drop(running);
+    all_done.recv().await;
+    ctx.close();
+    let written = writer
+        .await
+        .unwrap_or_else(|e| Err(DapError::Protocol(e.to_string())));
+    read.and(written)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::sync::{Barrier, Notify};
+    use tokio::task::JoinHandle;
+
+    use super::*;
+    use crate::Client;
+
+    /// run `handler` on one end of an in-memory pipe and a client on the other
+    pub(crate) fn connect<H: Handler>(
+        handler: H,
+        policy: Policy,
+    ) -> (Client, JoinHandle<DapResult<()>>) {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (reader, writer) = tokio::io::split(server_io);
+        let server = tokio::spawn(serve_with(reader, writer, handler, policy));
+        let (reader, writer) = tokio::io::split(client_io);
+        (Client::spawn(reader, writer), server)
+    }
+
+    /// context of a connection whose `initialize` with `client` capabilities is answered,
+    /// and receiver of messages it sends
+    pub(crate) fn initialized_ctx(
+        client: serde_json::Value,
+    ) -> (Ctx, mpsc::UnboundedReceiver<Message>) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let ctx = Ctx::new(tx);
+        let mut args = json!({"adapterID": "test"});
+        args.as_object_mut()
+            .unwrap()
+            .extend(client.as_object().cloned().unwrap_or_default());
+        let args: InitializeRequestArguments = serde_json::from_value(args).unwrap();
+        assert_eq!(ctx.receive(OneOf3::This(args.into_req(1))).len(), 1);
+        ctx.send(OneOf3::Among(ok(&Request {
+            arguments: None,
+            command: InitializeRequestArguments::COMMAND.to_string(),
+            seq: 1,
+            type_: "request".to_string(),
+        })))
+        .unwrap();
+        assert!(matches!(rx.try_recv(), Ok(OneOf3::Among(_))));
+        (ctx, rx)
+    }
+
+    pub(crate) fn ok(req: &Request) -> Response {
+        Response::ok_with::<(), _>(req.seq, &req.command, None)
+    }
+
+    pub(crate) async fn initialize(client: &Client) {
+        let args: InitializeRequestArguments =
+            serde_json::from_value(json!({"adapterID": "test"})).unwrap();
+        assert!(client.request(args).await.unwrap().success);
+    }
+
+    pub(crate) fn evaluate(expression: &str) -> EvaluateArguments {
+        serde_json::from_value(json!({ "expression": expression })).unwrap()
+    }
+
+    fn terminal_args() -> RunInTerminalRequestArguments {
+        RunInTerminalRequestArguments {
+            args: vec!["a.out".to_string()],
+            cwd: "/".to_string(),
+            env: None,
+            kind: None,
+            title: None,
+        }
+    }
+
+    /// `evaluate` runs `runInTerminal` as reverse request and answers its outcome
+    async fn reverse_handler(ctx: Ctx, req: Request) -> Response {
+        if !EvaluateArguments::can_cast(&req) {
+            return ok(&req);
+        }
+        let raw = ctx.request(terminal_args()).await.unwrap();
+        match ctx.run_in_terminal(terminal_args()).await {
+            Ok(body) => Response::ok_with(
+                req.seq,
+                &req.command,
+                json!({"result": body.process_id, "raw": raw.success}),
+            ),
+            Err(e) => Response::err(
+                req.seq,
+                &req.command,
+                e.to_string(),
+                json!({"raw": raw.message}),
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn reverse_request_round_trip() {
+        let (client, server) = connect(reverse_handler, Policy::default());
+        client.on_request(|req| {
+            assert_eq!(req.command, RunInTerminalRequestArguments::COMMAND);
+            let body = RunInTerminalResponseBody {
+                process_id: Some(42.0),
+                ..Default::default()
+            };
+            Response::ok_with(req.seq, &req.command, body)
+        });
+        initialize(&client).await;
+        let resp = client.request(evaluate("x")).await.unwrap();
+        assert!(resp.success);
+        assert_eq!(resp.body, Some(json!({"result": 42.0, "raw": true})));
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn reverse_request_error_response() {
+        // client without handler answers reverse requests with an error
+        let (client, server) = connect(reverse_handler, Policy::default());
+        initialize(&client).await;
+        let resp = client.request(evaluate("x")).await.unwrap();
+        assert!(!resp.success);
+        let message = resp.message.unwrap();
+        assert!(message.contains("runInTerminal failed"), "{}", message);
+        assert!(message.contains("not supported"), "{}", message);
+        assert_eq!(
+            resp.body,
+            Some(json!({"raw": "runInTerminal is not supported"}))
+        );
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn pause_runs_while_exclusive_request_is_running() {
+        let released = Arc::new(Notify::new());
+        let handler = {
+            let released = released.clone();
+            move |_ctx: Ctx, req: Request| {
+                let released = released.clone();
+                async move {
+                    if EvaluateArguments::can_cast(&req) {
+                        // only `pause` handler releases it
+                        released.notified().await;
+                    } else if PauseArguments::can_cast(&req) {
+                        released.notify_one();
+                    }
+                    ok(&req)
+                }
+            }
+        };
+        let policy = Policy::default().with(EvaluateArguments::COMMAND, Concurrency::Exclusive);
+        let (client, server) = connect(handler, policy);
+        initialize(&client).await;
+        let evaluate = client
+            .request(evaluate("loop"))
+            .timeout(Duration::from_secs(5));
+        let pause = client
+            .request(PauseArguments { thread_id: 1 })
+            .timeout(Duration::from_secs(5));
+        let (evaluate, pause) = tokio::join!(evaluate, pause);
+        assert!(pause.unwrap().success);
+        assert!(evaluate.unwrap().success);
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn shared_requests_run_together() {
+        let barrier = Arc::new(Barrier::new(2));
+        let handler = {
+            let barrier = barrier.clone();
+            move |_ctx: Ctx, req: Request| {
+                let barrier = barrier.clone();
+                async move {
+                    if EvaluateArguments::can_cast(&req) {
+                        // both evaluations must be running to pass
+                        barrier.wait().await;
+                    }
+                    ok(&req)
+                }
+            }
+        };
+        let (client, server) = connect(handler, Policy::default());
+        initialize(&client).await;
+        let first = client
+            .request(evaluate("a"))
+            .timeout(Duration::from_secs(5));
+        let second = client
+            .request(evaluate("b"))
+            .timeout(Duration::from_secs(5));
+        let (first, second) = tokio::join!(first, second);
+        assert!(first.unwrap().success && second.unwrap().success);
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn sequential_policy_keeps_order() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let handler = {
+            let log = log.clone();
+            move |_ctx: Ctx, req: Request| {
+                let log = log.clone();
+                async move {
+                    let expression = req.arguments.as_ref().map(|a| a["expression"].clone());
+                    log.lock().unwrap().push(format!("start {:?}", expression));
+                    if expression == Some(json!("slow")) {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                    }
+                    log.lock().unwrap().push(format!("end {:?}", expression));
+                    ok(&req)
+                }
+            }
+        };
+        let (client, server) = connect(handler, Policy::sequential());
+        initialize(&client).await;
+        log.lock().unwrap().clear();
+        let slow = client.request(evaluate("slow"));
+        let fast = client.request(evaluate("fast"));
+        let (slow, fast) = tokio::join!(slow, fast);
+        assert!(slow.unwrap().success && fast.unwrap().success);
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                r#"start Some(String("slow"))"#,
+                r#"end Some(String("slow"))"#,
+                r#"start Some(String("fast"))"#,
+                r#"end Some(String("fast"))"#,
+            ]
+        );
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn panicking_handler_fails_only_its_request() {
+        let handler = |_ctx: Ctx, req: Request| async move {
+            if EvaluateArguments::can_cast(&req) {
+                panic!("boom");
+            }
+            ok(&req)
+        };
+        let (client, server) = connect(handler, Policy::default());
+        initialize(&client).await;
+        let resp = client.request(evaluate("x")).await.unwrap();
+        assert!(!resp.success);
+        assert_eq!(
+            resp.message.as_deref(),
+            Some("internal error in evaluate: boom")
+        );
+        let body: ErrorResponseBody = serde_json::from_value(resp.body.unwrap()).unwrap();
+        let error = body.error.unwrap();
+        assert_eq!(error.id, HANDLER_PANICKED);
+        assert_eq!(error.show_user, Some(true));
+        assert_eq!(error.variables.unwrap()["reason"], "boom");
+
+        // server keeps serving
+        let resp = client
+            .request(ThreadsRequestArguments::default())
+            .await
+            .unwrap();
+        assert!(resp.success);
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,576 @@
+//! request handling as a `tower::Service`, enabled by `tower` feature
+//!
+//! [`ServiceHandler`] turns a layer stack around a [`Handler`] back into a [`Handler`] for
+//! [`serve`](crate::serve). handlers need the context of their connection to send events
+//! and reverse requests, so the innermost service takes a [`CtxRequest`] instead of a bare
+//! [`Request`]. a `Service<Request>` without context, e.g. from `tower::service_fn`, is
+//! plugged in with `map_request`, see [`ServiceHandler::from_service`].
+//!
+//! layers in this module work on both request types and never fail, problems are reported
+//! to client as error responses. tower has no tracing or panic catching middleware for
+//! non-http services, so [`TraceLayer`] and [`CatchPanicLayer`] are provided here, use
+//! `tower::timeout::TimeoutLayer` for timeouts, its error is answered as error response.
+//!
+//! ```ignore
+//! let layers = tower::ServiceBuilder::new()
+//!     .layer(TraceLayer)
+//!     .layer(CatchPanicLayer)
+//!     .layer(LogArgsLayer::default())
+//!     .layer(tower::timeout::TimeoutLayer::new(Duration::from_secs(10)))
+//!     .into_inner();
+//! serve(reader, writer, ServiceHandler::new(layers, handler)).await?;
+//! ```
+use std::any::Any;
+use std::borrow::Borrow;
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use dap_ty::{Request, Response};
+use serde_json::Value;
+use tower::{Layer, Service};
+use tracing::Instrument;
+
+use crate::server::{panic_reason, panic_response, BoxFuture, Ctx, Handler};
+
+type ServiceFuture<E> = Pin<Box<dyn Future<Output = Result<Response, E>> + Send>>;
+
+/// request passed to services, with context of the connection it came from
+#[derive(Clone)]
+pub struct CtxRequest {
+    pub ctx: Ctx,
+    pub request: Request,
+}
+
+impl Borrow<Request> for CtxRequest {
+    fn borrow(&self) -> &Request {
+        &self.request
+    }
+}
+
+/// [`Handler`] as a service
+pub struct HandlerService<H> {
+    handler: Arc<H>,
+}
+
+impl<H> Clone for HandlerService<H> {
+    fn clone(&self) -> Self {
+        Self {
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+impl<H: Handler> HandlerService<H> {
+    pub fn new(handler: Arc<H>) -> Self {
+        Self { handler }
+    }
+}
+
+impl<H: Handler> Service<CtxRequest> for HandlerService<H> {
+    type Response = Response;
+    type Error = std::convert::Infallible;
+    type Future = ServiceFuture<Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: CtxRequest) -> Self::Future {
+        let resp = self.handler.handle(req.ctx, req.request);
+        Box::pin(async move { Ok(resp.await) })
+    }
+}
+
+/// [`Handler`] running requests through `layer` wrapped around `handler`
+///
+/// layer stack is built once, each request is handled by a clone of it, so state of a layer
+/// is kept across requests as long as its clones share it. service error is answered with
+/// an error response
+pub struct ServiceHandler<S> {
+    service: S,
+}
+
+impl<S> ServiceHandler<S> {
+    pub fn new<L, H>(layer: L, handler: H) -> Self
+    where
+        L: Layer<HandlerService<H>, Service = S>,
+        H: Handler,
+    {
+        Self {
+            service: layer.layer(HandlerService::new(Arc::new(handler))),
+        }
+    }
+
+    /// use a complete service stack, a `Service<Request>` is adapted with
+    /// `map_request(|req: CtxRequest| req.request)` of `tower::ServiceExt`
+    pub fn from_service(service: S) -> Self {
+        Self { service }
+    }
+}
+
+impl<S> Handler for ServiceHandler<S>
+where
+    S: Service<CtxRequest, Response = Response> + Clone + Send + Sync + 'static,
+    S::Error: fmt::Display + Send,
+    S::Future: Send,
+{
+    fn handle(&self, ctx: Ctx, request: Request) -> BoxFuture<Response> {
+        let mut service = self.service.clone();
+        Box::pin(async move {
+            let (seq, command) = (request.seq, request.command.clone());
+            let resp = match poll_fn(|cx| service.poll_ready(cx)).await {
+                Ok(()) => service.call(CtxRequest { ctx, request }).await,
+                Err(e) => Err(e),
+            };
+            resp.unwrap_or_else(|e| Response::err::<(), _>(seq, &command, e.to_string(), None))
+        })
+    }
+}
+
+/// run each request in a `request` span with its `seq` and `command`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceLayer;
+
+impl<S> Layer<S> for TraceLayer {
+    type Service = Trace<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Trace { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Trace<S> {
+    inner: S,
+}
+
+impl<S, R> Service<R> for Trace<S>
+where
+    R: Borrow<Request>,
+    S: Service<R, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = ServiceFuture<S::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let request: &Request = req.borrow();
+        let span = tracing::info_span!("request", seq = request.seq, command = %request.command);
+        let fut = self.inner.call(req);
+        Box::pin(
+            async move {
+                let resp = fut.await;
+                if let Ok(resp) = &resp {
+                    tracing::debug!(success = resp.success, "response");
+                }
+                resp
+            }
+            .instrument(span),
+        )
+    }
+}
+
+/// answer request with an error response if inner service panics
+///
+/// [`serve`](crate::serve) catches panics of handlers too, this layer lets outer layers see
+/// the error response, e.g. to record it in metrics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CatchPanicLayer;
+
+impl<S> Layer<S> for CatchPanicLayer {
+    type Service = CatchPanic<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CatchPanic { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CatchPanic<S> {
+    inner: S,
+}
+
+fn caught(seq: i64, command: &str, payload: Box<dyn Any + Send>) -> Response {
+    let reason = panic_reason(payload);
+    tracing::error!("service of {} {} panicked: {}", seq, command, reason);
+    panic_response(seq, command, reason)
+}
+
+impl<S, R> Service<R> for CatchPanic<S>
+where
+    R: Borrow<Request>,
+    S: Service<R, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = ServiceFuture<S::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let request: &Request = req.borrow();
+        let (seq, command) = (request.seq, request.command.clone());
+        let mut fut = match catch_unwind(AssertUnwindSafe(|| self.inner.call(req))) {
+            Ok(fut) => Box::pin(fut),
+            Err(payload) => {
+                let resp = caught(seq, &command, payload);
+                return Box::pin(async move { Ok(resp) });
+            }
+        };
+        Box::pin(async move {
+            let polled =
+                poll_fn(
+                    |cx| match catch_unwind(AssertUnwindSafe(|| fut.as_mut().poll(cx))) {
+                        Ok(Poll::Ready(resp)) => Poll::Ready(Ok(resp)),
+                        Ok(Poll::Pending) => Poll::Pending,
+                        Err(payload) => Poll::Ready(Err(payload)),
+                    },
+                )
+                .await;
+            polled.unwrap_or_else(|payload| Ok(caught(seq, &command, payload)))
+        })
+    }
+}
+
+/// outcome of a handled request, reported by [`MetricsLayer`]
+#[derive(Debug, Clone)]
+pub struct RequestMetric {
+    pub command: String,
+    /// false for error response or service error
+    pub success: bool,
+    pub elapsed: Duration,
+}
+
+type Record = Arc<dyn Fn(&RequestMetric) + Send + Sync>;
+
+/// call `record` after each request with its command, outcome and duration
+#[derive(Clone)]
+pub struct MetricsLayer {
+    record: Record,
+}
+
+impl MetricsLayer {
+    pub fn new<F>(record: F) -> Self
+    where
+        F: Fn(&RequestMetric) + Send + Sync + 'static,
+    {
+        Self {
+            record: Arc::new(record),
+        }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = Metrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Metrics {
+            inner,
+            record: self.record.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Metrics<S> {
+    inner: S,
+    record: Record,
+}
+
+impl<S, R> Service<R> for Metrics<S>
+where
+    R: Borrow<Request>,
+    S: Service<R, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = ServiceFuture<S::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let command = Borrow::<Request>::borrow(&req).command.clone();
+        let record = self.record.clone();
+        let start = Instant::now();
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let resp = fut.await;
+            record(&RequestMetric {
+                command,
+                success: matches!(&resp, Ok(resp) if resp.success),
+                elapsed: start.elapsed(),
+            });
+            resp
+        })
+    }
+}
+
+/// argument keys redacted by default [`LogArgsLayer`], matched case insensitively
+pub const REDACTED_KEYS: &[&str] = &["password", "token", "secret", "apiKey", "env"];
+
+/// placeholder of redacted values
+pub const REDACTED: &str = "<redacted>";
+
+/// replace values of `keys` at any depth of `value` with [`REDACTED`]
+pub fn redact(value: &mut Value, keys: &[String]) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if keys.iter().any(|k| k.eq_ignore_ascii_case(key)) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value, keys);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|v| redact(v, keys)),
+        _ => {}
+    }
+}
+
+/// log request arguments at debug level, values of sensitive keys are redacted
+#[derive(Debug, Clone)]
+pub struct LogArgsLayer {
+    keys: Arc<Vec<String>>,
+}
+
+impl Default for LogArgsLayer {
+    fn default() -> Self {
+        Self::new(REDACTED_KEYS.iter().copied())
+    }
+}
+
+impl LogArgsLayer {
+    pub fn new<'a>(keys: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            keys: Arc::new(keys.into_iter().map(String::from).collect()),
+        }
+    }
+}
+
+impl<S> Layer<S> for LogArgsLayer {
+    type Service = LogArgs<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LogArgs {
+            inner,
+            keys: self.keys.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogArgs<S> {
+    inner: S,
+    keys: Arc<Vec<String>>,
+}
+
+impl<S, R> Service<R> for LogArgs<S>
+where
+    R: Borrow<Request>,
+    S: Service<R, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        if tracing::enabled!(tracing::Level::DEBUG) {
+            let request: &Request = req.borrow();
+            let mut args = request.arguments.clone().unwrap_or(Value::Null);
+            redact(&mut args, &self.keys);
+            tracing::debug!(
+                seq = request.seq,
+                command = %request.command,
+                arguments = %args,
+                "request"
+            );
+        }
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    use serde_json::json;
+    use tokio::sync::mpsc;
+    use tower::timeout::TimeoutLayer;
+    use tower::{service_fn, ServiceBuilder, ServiceExt};
+
+    use super::*;
+    use crate::server::HANDLER_PANICKED;
+
+    fn request(command: &str, arguments: Value) -> Request {
+        serde_json::from_value(json!({
+            "seq": 7,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        }))
+        .unwrap()
+    }
+
+    fn ok(req: &Request) -> Response {
+        Response::ok_with::<(), _>(req.seq, &req.command, None)
+    }
+
+    /// context of a connection nobody listens to
+    fn ctx() -> Ctx {
+        Ctx::new(mpsc::unbounded_channel().0)
+    }
+
+    fn assert_panic_response(resp: &Response, reason: &str) {
+        assert!(!resp.success);
+        let error = &resp.body.as_ref().unwrap()["error"];
+        assert_eq!(error["id"], HANDLER_PANICKED);
+        assert_eq!(error["showUser"], true);
+        assert_eq!(error["variables"]["reason"], reason);
+    }
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn redact_nested_keys() {
+        let mut value = json!({
+            "program": "a.out",
+            "Password": "p",
+            "args": [{"apikey": "k", "keep": 1}],
+        });
+        redact(&mut value, &["password".to_string(), "apiKey".to_string()]);
+        assert_eq!(
+            value,
+            json!({
+                "program": "a.out",
+                "Password": REDACTED,
+                "args": [{"apikey": REDACTED, "keep": 1}],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn log_args_redacts_sensitive_values() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let service = LogArgsLayer::default().layer(service_fn(|req: Request| async move {
+            // inner service still sees original arguments
+            assert_eq!(req.arguments.as_ref().unwrap()["env"]["A"], "1");
+            Ok::<_, Infallible>(ok(&req))
+        }));
+        let args = json!({
+            "program": "a.out",
+            "env": {"A": "1"},
+            "nested": [{"Token": "t0ps3cret"}],
+        });
+        let resp = service.oneshot(request("launch", args)).await.unwrap();
+        assert!(resp.success);
+
+        let logged = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(logged.contains(r#""program":"a.out""#), "{}", logged);
+        assert!(logged.contains(REDACTED), "{}", logged);
+        assert!(!logged.contains("t0ps3cret"), "{}", logged);
+        assert!(!logged.contains(r#""A":"1""#), "{}", logged);
+    }
+
+    #[tokio::test]
+    async fn timeout_is_answered_with_error_response() {
+        let handler = ServiceHandler::new(
+            TimeoutLayer::new(Duration::from_millis(20)),
+            |_ctx: Ctx, req: Request| async move {
+                if req.command == "evaluate" {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                }
+                ok(&req)
+            },
+        );
+        let resp = handler
+            .handle(ctx(), request("evaluate", Value::Null))
+            .await;
+        assert!(!resp.success);
+        assert_eq!(resp.request_seq, 7);
+        assert_eq!(resp.message.as_deref(), Some("request timed out"));
+        let resp = handler.handle(ctx(), request("threads", Value::Null)).await;
+        assert!(resp.success);
+    }
+
+    #[tokio::test]
+    async fn catch_panic_in_handler() {
+        let handler = ServiceHandler::new(CatchPanicLayer, |_ctx: Ctx, req: Request| async move {
+            if req.command == "evaluate" {
+                panic!("in future");
+            }
+            ok(&req)
+        });
+        let resp = handler
+            .handle(ctx(), request("evaluate", Value::Null))
+            .await;
+        assert_panic_response(&resp, "in future");
+        let resp = handler.handle(ctx(), request("threads", Value::Null)).await;
+        assert!(resp.success);
+    }
+
+    #[tokio::test]
+    async fn catch_panic_in_call_of_plain_service() {
+        // panics before returning its future
+        let service = service_fn(|req: Request| {
+            if req.command == "evaluate" {
+                panic!("in call");
+            }
+            std::future::ready(Ok::<_, Infallible>(ok(&req)))
+        });
+        let service = ServiceBuilder::new()
+            .map_request(|req: CtxRequest| req.request)
+            .layer(TraceLayer)
+            .layer(CatchPanicLayer)
+            .service(service);
+        let handler = ServiceHandler::from_service(service);
+        let resp = handler
+            .handle(ctx(), request("evaluate", Value::Null))
+            .await;
+        assert_panic_response(&resp, "in call");
+        let resp = handler.handle(ctx(), request("threads", Value::Null)).await;
+        assert!(resp.success);
+    }
+}
@@ -0,0 +1,627 @@
+//! adapter side session lifecycle
+//!
+//! [`Session`] checks every message an adapter receives or sends against the lifecycle
+//! defined by the spec:
+//!
+//! 1. client sends `initialize`, nothing but `disconnect` is accepted before it
+//! 2. adapter answers `initialize`, an `initialized` event sent before the answer is queued
+//!    and released right after it
+//! 3. client sends configuration requests, then `configurationDone`, `launch` or `attach`
+//!    received before `configurationDone` is queued and released right after it
+//! 4. adapter sends `terminated`, no more events are allowed after it
+//! 5. client sends `disconnect`, in any state, no more requests are accepted after it
+use std::fmt;
+
+use dap_ty::{
+    AttachRequestArguments, ConfigurationDoneArguments, DisconnectArguments, Event, FromEvent,
+    FromReq, InitializeRequestArguments, InitializedEventBody, LaunchRequestArguments, OneOf3,
+    Request, Response, TerminatedEventBody,
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::DapError;
+
+type Message = OneOf3<Request, Response, Event>;
+
+/// lifecycle state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// waiting `initialize` request
+    Uninitialized,
+    /// `initialize` received, waiting adapter's response
+    Initializing,
+    /// `initialize` answered, waiting `configurationDone`
+    Configuring,
+    /// `configurationDone` received, waiting `launch` or `attach` response
+    Configured,
+    /// `launch` or `attach` answered successfully
+    Running,
+    /// `terminated` event sent
+    Terminated,
+    /// `disconnect` received
+    Disconnected,
+}
+
+/// a message rejected by [`Session`]
+#[derive(Debug, Clone)]
+pub struct SessionError {
+    pub state: State,
+    pub reason: String,
+    pub message: Box<Message>,
+}
+
+impl SessionError {
+    /// error response to send back if rejected message is a request
+    pub fn error_response(&self) -> Option<Response> {
+        match self.message.as_ref() {
+            OneOf3::This(req) => Some(Response::err::<(), _>(
+                req.seq,
+                &req.command,
+                self.reason.clone(),
+                None,
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (state {:?})", self.reason, self.state)
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl From<SessionError> for DapError {
+    fn from(e: SessionError) -> Self {
+        DapError::Protocol(e.to_string())
+    }
+}
+
+fn parse_args<T: DeserializeOwned + Default>(req: &Request) -> T {
+    req.arguments
+        .clone()
+        .and_then(|args| serde_json::from_value(args).ok())
+        .unwrap_or_default()
+}
+
+/// lifecycle state machine, see [module level doc](self)
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: State,
+    client: Option<InitializeRequestArguments>,
+    launch: Option<Request>,
+    disconnect: Option<DisconnectArguments>,
+    terminated: Option<TerminatedEventBody>,
+    /// `initialize` request seq, used to find its response
+    initialize_seq: Option<i64>,
+    /// `launch` or `attach` released to handler, waiting response
+    launch_seq: Option<i64>,
+    queued_launch: Option<Request>,
+    queued_initialized: Option<Event>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            state: State::Uninitialized,
+            client: None,
+            launch: None,
+            disconnect: None,
+            terminated: None,
+            initialize_seq: None,
+            launch_seq: None,
+            queued_launch: None,
+            queued_initialized: None,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// arguments of `initialize` request, available after it is received
+    pub fn client(&self) -> Option<&InitializeRequestArguments> {
+        self.client.as_ref()
+    }
+
+    /// arguments of `launch` request parsed with adapter specific attributes `T`,
+    /// `None` for attach session
+    pub fn launch_args<T: DeserializeOwned>(&self) -> Option<LaunchRequestArguments<T>> {
+        self.launch
+            .as_ref()
+            .filter(|req| <LaunchRequestArguments>::can_cast(req))
+            .and_then(|req| req.arguments.clone())
+            .and_then(|args| serde_json::from_value(args).ok())
+    }
+
+    /// arguments of `attach` request parsed with adapter specific attributes `T`,
+    /// `None` for launch session
+    pub fn attach_args<T: DeserializeOwned>(&self) -> Option<AttachRequestArguments<T>> {
+        self.launch
+            .as_ref()
+            .filter(|req| <AttachRequestArguments>::can_cast(req))
+            .and_then(|req| req.arguments.clone())
+            .and_then(|args| serde_json::from_value(args).ok())
+    }
+
+    /// `__restart` attribute of `launch` or `attach`, i.e. `restart` of `terminated` event
+    /// sent by previous session
+    pub fn restart_data(&self) -> Option<serde_json::Value> {
+        self.launch
+            .as_ref()
+            .and_then(|req| req.arguments.as_ref())
+            .and_then(|args| args.get("__restart"))
+            .filter(|data| !data.is_null())
+            .cloned()
+    }
+
+    /// arguments of `disconnect` request, available after it is received
+    pub fn disconnect_args(&self) -> Option<&DisconnectArguments> {
+        self.disconnect.as_ref()
+    }
+
+    /// body of `terminated` event, available after it is sent
+    pub fn terminated(&self) -> Option<&TerminatedEventBody> {
+        self.terminated.as_ref()
+    }
+
+    fn reject(&self, reason: &str, msg: Message) -> SessionError {
+        SessionError {
+            state: self.state,
+            reason: reason.to_string(),
+            message: Box::new(msg),
+        }
+    }
+
+    /// check message received from client, return messages which should be handled now
+    ///
+    /// returned list is empty if message is queued, and contains queued `launch` or `attach`
+    /// after `configurationDone`
+    pub fn receive(&mut self, msg: Message) -> Result<Vec<Message>, SessionError> {
+        let req = match msg {
+            OneOf3::This(req) => req,
+            // responses of reverse requests and events are not part of lifecycle
+            other => return Ok(vec![other]),
+        };
+        let is_launch =
+            <LaunchRequestArguments>::can_cast(&req) || <AttachRequestArguments>::can_cast(&req);
+        match self.state {
+            State::Disconnected => Err(self.reject("session is disconnected", OneOf3::This(req))),
+            _ if DisconnectArguments::can_cast(&req) => {
+                self.disconnect = Some(parse_args(&req));
+                self.state = State::Disconnected;
+                Ok(vec![OneOf3::This(req)])
+            }
+            State::Uninitialized if InitializeRequestArguments::can_cast(&req) => {
+                self.client = req
+                    .arguments
+                    .clone()
+                    .and_then(|args| serde_json::from_value(args).ok());
+                self.initialize_seq = Some(req.seq);
+                self.state = State::Initializing;
+                Ok(vec![OneOf3::This(req)])
+            }
+            State::Uninitialized | State::Initializing => {
+                Err(self.reject("initialize request must complete first", OneOf3::This(req)))
+            }
+            _ if InitializeRequestArguments::can_cast(&req) => {
+                Err(self.reject("session already initialized", OneOf3::This(req)))
+            }
+            State::Configuring if is_launch => {
+                if self.queued_launch.is_some() || self.launch.is_some() {
+                    return Err(self.reject("launch or attach already received", OneOf3::This(req)));
+                }
+                self.queued_launch = Some(req);
+                Ok(vec![])
+            }
+            State::Configuring if ConfigurationDoneArguments::can_cast(&req) => {
+                self.state = State::Configured;
+                let mut ready = vec![OneOf3::This(req)];
+                if let Some(launch) = self.queued_launch.take() {
+                    self.launch_seq = Some(launch.seq);
+                    self.launch = Some(launch.clone());
+                    ready.push(OneOf3::This(launch));
+                }
+                Ok(ready)
+            }
+            _ if ConfigurationDoneArguments::can_cast(&req) => {
+                Err(self.reject("configurationDone already received", OneOf3::This(req)))
+            }
+            State::Configured if is_launch && self.launch.is_none() => {
+                self.launch_seq = Some(req.seq);
+                self.launch = Some(req.clone());
+                Ok(vec![OneOf3::This(req)])
+            }
+            _ if is_launch => {
+                Err(self.reject("launch or attach already received", OneOf3::This(req)))
+            }
+            _ => Ok(vec![OneOf3::This(req)]),
+        }
+    }
+
+    /// check message adapter is about to send, return messages which should be written now
+    ///
+    /// returned list is empty if message is queued, and contains queued `initialized` event
+    /// after `initialize` response
+    pub fn send(&mut self, msg: Message) -> Result<Vec<Message>, SessionError> {
+        match msg {
+            OneOf3::Among(resp) => Ok(self.send_response(resp)),
+            OneOf3::Other(event) => self.send_event(event),
+            req => Ok(vec![req]),
+        }
+    }
+
+    fn send_response(&mut self, resp: Response) -> Vec<Message> {
+        let mut ready = vec![];
+        if Some(resp.request_seq) == self.initialize_seq && self.state == State::Initializing {
+            self.initialize_seq = None;
+            if resp.success {
+                self.state = State::Configuring;
+                ready.push(OneOf3::Among(resp));
+                ready.extend(self.queued_initialized.take().map(OneOf3::Other));
+            } else {
+                self.state = State::Uninitialized;
+                self.client = None;
+                ready.push(OneOf3::Among(resp));
+            }
+            return ready;
+        }
+        if Some(resp.request_seq) == self.launch_seq {
+            self.launch_seq = None;
+            if !resp.success {
+                // allow client to retry `launch` or `attach`
+                self.launch = None;
+            } else if self.state == State::Configured {
+                self.state = State::Running;
+            }
+        }
+        ready.push(OneOf3::Among(resp));
+        ready
+    }
+
+    fn send_event(&mut self, event: Event) -> Result<Vec<Message>, SessionError> {
+        match self.state {
+            State::Terminated | State::Disconnected if self.terminated.is_some() => {
+                Err(self.reject("event after terminated", OneOf3::Other(event)))
+            }
+            State::Uninitialized => {
+                Err(self.reject("event before initialize request", OneOf3::Other(event)))
+            }
+            State::Initializing if InitializedEventBody::can_cast(&event) => {
+                self.queued_initialized = Some(event);
+                Ok(vec![])
+            }
+            _ if TerminatedEventBody::can_cast(&event) => {
+                self.terminated = Some(
+                    event
+                        .body
+                        .clone()
+                        .and_then(|body| serde_json::from_value(body).ok())
+                        .unwrap_or_default(),
+                );
+                if self.state != State::Disconnected {
+                    self.state = State::Terminated;
+                }
+                Ok(vec![OneOf3::Other(event)])
+            }
+            _ => Ok(vec![OneOf3::Other(event)]),
+        }
+    }
+}
+
+#[cfg(feature = "blocking")]
+mod blocking_impl {
+    use std::collections::VecDeque;
+    use std::io::{Read, Write};
+
+    use super::{Message, Session};
+    use crate::{error::DapResult, Codec};
+
+    /// [`Codec`] which enforces session lifecycle
+    ///
+    /// out of order requests are answered with error response and skipped,
+    /// out of order outgoing messages are returned as error
+    pub struct SessionCodec<S> {
+        codec: Codec<S>,
+        session: Session,
+        inbox: VecDeque<Message>,
+    }
+
+    impl<S: Read + Write> SessionCodec<S> {
+        pub fn new(codec: Codec<S>) -> Self {
+            Self {
+                codec,
+                session: Session::new(),
+                inbox: VecDeque::new(),
+            }
+        }
+
+        pub fn session(&self) -> &Session {
+            &self.session
+        }
+
+        /// get mutable ref of underlying codec
+        pub fn codec_mut(&mut self) -> &mut Codec<S> {
+            &mut self.codec
+        }
+
+        /// read next message allowed in current state, `None` on clean eof
+        pub fn receive(&mut self) -> DapResult<Option<Message>> {
+            loop {
+                if let Some(msg) = self.inbox.pop_front() {
+                    return Ok(Some(msg));
+                }
+                let msg = match self.codec.receive()? {
+                    Some(msg) => msg,
+                    None => return Ok(None),
+                };
+                match self.session.receive(msg) {
+                    Ok(ready) => self.inbox.extend(ready),
+                    Err(e) => {
+                        tracing::warn!("{}", e);
+                        if let Some(resp) = e.error_response() {
+                            self.codec.send_resp(resp)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        /// write message, or queue it until it is allowed
+        pub fn send(&mut self, msg: Message) -> DapResult<()> {
+            for msg in self.session.send(msg)? {
+                self.codec.send(msg)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "blocking")]
+pub use blocking_impl::SessionCodec;
+
+#[cfg(feature = "async")]
+mod async_impl {
+    use std::collections::VecDeque;
+
+    use tokio::io::{AsyncRead, AsyncWrite};
+
+    use super::{Message, Session};
+    use crate::{error::DapResult, AsyncCodec};
+
+    /// async version of [`SessionCodec`](super::SessionCodec)
+    pub struct AsyncSessionCodec<S> {
+        codec: AsyncCodec<S>,
+        session: Session,
+        inbox: VecDeque<Message>,
+    }
+
+    impl<S: AsyncRead + AsyncWrite + Unpin> AsyncSessionCodec<S> {
+        pub fn new(codec: AsyncCodec<S>) -> Self {
+            Self {
+                codec,
+                session: Session::new(),
+                inbox: VecDeque::new(),
+            }
+        }
+
+        pub fn session(&self) -> &Session {
+            &self.session
+        }
+
+        /// get mutable ref of underlying codec
+        pub fn codec_mut(&mut self) -> &mut AsyncCodec<S> {
+            &mut self.codec
+        }
+
+        /// read next message allowed in current state, `None` on clean eof
+        pub async fn receive(&mut self) -> DapResult<Option<Message>> {
+            loop {
+                if let Some(msg) = self.inbox.pop_front() {
+                    return Ok(Some(msg));
+                }
+                let msg = match self.codec.receive().await? {
+                    Some(msg) => msg,
+                    None => return Ok(None),
+                };
+                match self.session.receive(msg) {
+                    Ok(ready) => self.inbox.extend(ready),
+                    Err(e) => {
+                        tracing::warn!("{}", e);
+                        if let Some(resp) = e.error_response() {
+                            self.codec.send_resp(resp).await?;
+                        }
+                    }
+                }
+            }
+        }
+
+        /// write message, or queue it until it is allowed
+        pub async fn send(&mut self, msg: Message) -> DapResult<()> {
+            for msg in self.session.send(msg)? {
+                self.codec.send(msg).await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub use async_impl::AsyncSessionCodec;
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn request(seq: i64, command: &str) -> Message {
+        serde_json::from_value(json!({"seq": seq, "type": "request", "command": command})).unwrap()
+    }
+
+    fn response(request_seq: i64, command: &str, success: bool) -> Message {
+        serde_json::from_value(json!({
+            "seq": 100 + request_seq,
+            "type": "response",
+            "request_seq": request_seq,
+            "command": command,
+            "success": success,
+        }))
+        .unwrap()
+    }
+
+    fn event(name: &str) -> Message {
+        serde_json::from_value(json!({"seq": 0, "type": "event", "event": name})).unwrap()
+    }
+
+    fn commands(msgs: &[Message]) -> Vec<&str> {
+        msgs.iter()
+            .map(|msg| match msg {
+                OneOf3::This(req) => req.command.as_str(),
+                OneOf3::Among(resp) => resp.command.as_str(),
+                OneOf3::Other(event) => event.event.as_str(),
+            })
+            .collect()
+    }
+
+    /// session with `initialize` answered
+    fn configuring() -> Session {
+        let mut session = Session::new();
+        session.receive(request(1, "initialize")).unwrap();
+        session.send(response(1, "initialize", true)).unwrap();
+        assert_eq!(session.state(), State::Configuring);
+        session
+    }
+
+    #[test]
+    fn full_lifecycle() {
+        let mut session = configuring();
+        assert_eq!(
+            commands(&session.receive(request(2, "setBreakpoints")).unwrap()),
+            ["setBreakpoints"]
+        );
+        session.receive(request(3, "configurationDone")).unwrap();
+        assert_eq!(session.state(), State::Configured);
+        session.receive(request(4, "launch")).unwrap();
+        session.send(response(4, "launch", true)).unwrap();
+        assert_eq!(session.state(), State::Running);
+        session.send(event("terminated")).unwrap();
+        assert_eq!(session.state(), State::Terminated);
+        assert!(session.terminated().is_some());
+        session.receive(request(5, "disconnect")).unwrap();
+        assert_eq!(session.state(), State::Disconnected);
+    }
+
+    #[test]
+    fn requests_before_initialize_are_rejected() {
+        let mut session = Session::new();
+        let err = session.receive(request(1, "threads")).unwrap_err();
+        assert_eq!(err.state, State::Uninitialized);
+        let resp = err.error_response().unwrap();
+        assert!(!resp.success);
+        assert_eq!(resp.request_seq, 1);
+
+        session.receive(request(2, "initialize")).unwrap();
+        assert!(session.receive(request(3, "threads")).is_err());
+        assert!(session.receive(request(4, "initialize")).is_err());
+    }
+
+    #[test]
+    fn failed_initialize_resets() {
+        let mut session = Session::new();
+        session.receive(request(1, "initialize")).unwrap();
+        session.send(response(1, "initialize", false)).unwrap();
+        assert_eq!(session.state(), State::Uninitialized);
+        assert!(session.client().is_none());
+        assert!(session.receive(request(2, "initialize")).is_ok());
+    }
+
+    #[test]
+    fn initialized_event_waits_initialize_response() {
+        let mut session = Session::new();
+        session.receive(request(1, "initialize")).unwrap();
+        assert!(session.send(event("initialized")).unwrap().is_empty());
+        let ready = session.send(response(1, "initialize", true)).unwrap();
+        assert_eq!(commands(&ready), ["initialize", "initialized"]);
+    }
+
+    #[test]
+    fn launch_waits_configuration_done() {
+        let mut session = configuring();
+        let launch = serde_json::from_value(json!({
+            "seq": 2,
+            "type": "request",
+            "command": "launch",
+            "arguments": {"noDebug": true, "program": "a.out"},
+        }))
+        .unwrap();
+        assert!(session.receive(launch).unwrap().is_empty());
+        assert!(session.receive(request(3, "attach")).is_err());
+        let ready = session.receive(request(4, "configurationDone")).unwrap();
+        assert_eq!(commands(&ready), ["configurationDone", "launch"]);
+        let args = session.launch_args::<serde_json::Value>().unwrap();
+        assert_eq!(args.no_debug, Some(true));
+        assert!(session.attach_args::<serde_json::Value>().is_none());
+        assert!(session.receive(request(5, "configurationDone")).is_err());
+        assert!(session.receive(request(6, "launch")).is_err());
+    }
+
+    #[test]
+    fn failed_launch_can_retry() {
+        let mut session = configuring();
+        session.receive(request(2, "configurationDone")).unwrap();
+        session.receive(request(3, "attach")).unwrap();
+        session.send(response(3, "attach", false)).unwrap();
+        assert_eq!(session.state(), State::Configured);
+        assert!(session.attach_args::<serde_json::Value>().is_none());
+
+        session.receive(request(4, "attach")).unwrap();
+        session.send(response(4, "attach", true)).unwrap();
+        assert_eq!(session.state(), State::Running);
+        assert!(session.receive(request(5, "launch")).is_err());
+    }
+
+    #[test]
+    fn disconnect_accepted_before_initialized() {
+        let mut session = Session::new();
+        session.receive(request(1, "disconnect")).unwrap();
+        assert_eq!(session.state(), State::Disconnected);
+        assert!(session.receive(request(2, "initialize")).is_err());
+
+        let mut session = Session::new();
+        session.receive(request(1, "initialize")).unwrap();
+        session.receive(request(2, "disconnect")).unwrap();
+        assert_eq!(session.state(), State::Disconnected);
+        assert!(session.disconnect_args().is_some());
+        // late initialize response no longer changes state
+        session.send(response(1, "initialize", true)).unwrap();
+        assert_eq!(session.state(), State::Disconnected);
+    }
+
+    #[test]
+    fn events_after_terminated_are_rejected() {
+        let mut session = Session::new();
+        assert!(session.send(event("output")).is_err());
+        let mut session = configuring();
+        session.send(event("terminated")).unwrap();
+        assert!(session.send(event("output")).is_err());
+    }
+
+    #[test]
+    fn nothing_accepted_after_disconnect() {
+        let mut session = configuring();
+        session.receive(request(2, "disconnect")).unwrap();
+        assert_eq!(session.state(), State::Disconnected);
+        assert!(session.receive(request(3, "threads")).is_err());
+        // responses of reverse requests pass through
+        assert!(session.receive(response(7, "runInTerminal", true)).is_ok());
+    }
+}
@@ -0,0 +1,413 @@
+//! capture state of a stopped debuggee: threads, stack frames, scopes and variables
+//!
+//! [`StoppedSnapshot`] sends `threads`, then `stackTrace` of every thread, `scopes` of every
+//! frame and `variables` of every scope down to a depth limit. requests are sent
+//! concurrently, at most [`max_concurrency`](StoppedSnapshot::max_concurrency) at a time,
+//! and the first failure stops the capture. the resulting [`Snapshot`] is serializable, e.g. for crash
+//! reports or golden files, see [`StoppedSnapshot::strip_references`] for the latter.
+use std::sync::Arc;
+
+use dap_ty::{
+    FromReq, Scope, ScopesArguments, StackFrame, StackTraceArguments, Thread,
+    ThreadsRequestArguments, Variable, VariablesArguments,
+};
+use futures::future::try_join_all;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::client::Client;
+use crate::error::{DapError, DapResult};
+use crate::server::BoxFuture;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub threads: Vec<ThreadSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ThreadSnapshot {
+    pub thread: Thread,
+    pub frames: Vec<FrameSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FrameSnapshot {
+    pub frame: StackFrame,
+    pub scopes: Vec<ScopeSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ScopeSnapshot {
+    pub scope: Scope,
+    /// empty if scope is expensive and expensive scopes are skipped
+    pub variables: Vec<VariableSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct VariableSnapshot {
+    pub variable: Variable,
+    /// empty for scalars and below depth limit
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<VariableSnapshot>,
+}
+
+/// default of [`StoppedSnapshot::max_concurrency`]
+pub const MAX_CONCURRENCY: usize = 16;
+
+/// builder of a [`Snapshot`]
+#[derive(Debug, Clone)]
+pub struct StoppedSnapshot {
+    threads: Option<Vec<i64>>,
+    max_frames: Option<i64>,
+    max_depth: usize,
+    max_variables: Option<i64>,
+    expensive: bool,
+    strip_references: bool,
+    max_concurrency: usize,
+    /// requests in flight, shared by all clones made during one capture
+    permits: Arc<Semaphore>,
+}
+
+impl Default for StoppedSnapshot {
+    fn default() -> Self {
+        Self {
+            threads: None,
+            max_frames: Some(20),
+            max_depth: 2,
+            max_variables: Some(100),
+            expensive: false,
+            strip_references: false,
+            max_concurrency: MAX_CONCURRENCY,
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENCY)),
+        }
+    }
+}
+
+impl StoppedSnapshot {
+    /// at most 20 frames per thread, 100 variables per container, 2 levels of
+    /// variables below scopes, expensive scopes skipped, [`MAX_CONCURRENCY`] requests in
+    /// flight
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// only capture these threads
+    pub fn threads(mut self, ids: impl IntoIterator<Item = i64>) -> Self {
+        self.threads = Some(ids.into_iter().collect());
+        self
+    }
+
+    /// limit frames per thread, `None` for all frames
+    pub fn max_frames(mut self, max: Option<i64>) -> Self {
+        self.max_frames = max;
+        self
+    }
+
+    /// levels of variables fetched below a scope, 0 captures scopes only
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// limit variables per scope or container, `None` for all variables
+    pub fn max_variables(mut self, max: Option<i64>) -> Self {
+        self.max_variables = max;
+        self
+    }
+
+    /// also fetch variables of scopes marked `expensive`
+    pub fn expensive(mut self, enable: bool) -> Self {
+        self.expensive = enable;
+        self
+    }
+
+    /// set `id` of frames and `variablesReference` of scopes and variables to 0 in the
+    /// snapshot, they are assigned by adapter and change between runs
+    pub fn strip_references(mut self, enable: bool) -> Self {
+        self.strip_references = enable;
+        self
+    }
+
+    /// limit requests in flight, at least 1
+    pub fn max_concurrency(mut self, max: usize) -> Self {
+        self.max_concurrency = max.max(1);
+        self
+    }
+
+    /// capture current state, debuggee is expected to stay stopped meanwhile
+    pub async fn take(&self, client: &Client) -> DapResult<Snapshot> {
+        let this = Self {
+            permits: Arc::new(Semaphore::new(self.max_concurrency)),
+            ..self.clone()
+        };
+        let mut threads = this
+            .call(client, ThreadsRequestArguments::default())
+            .await?
+            .threads;
+        if let Some(ids) = &this.threads {
+            threads.retain(|t| ids.contains(&t.id));
+        }
+        let threads = try_join_all(
+            threads
+                .into_iter()
+                .map(|thread| this.clone().thread(client.clone(), thread)),
+        )
+        .await?;
+        Ok(Snapshot { threads })
+    }
+
+    /// send a request once a permit is available
+    async fn call<T: FromReq>(&self, client: &Client, args: T) -> DapResult<T::Ret>
+    where
+        T::Ret: DeserializeOwned,
+    {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("snapshot semaphore is never closed");
+        client.call(args).await
+    }
+
+    async fn thread(self, client: Client, thread: Thread) -> DapResult<ThreadSnapshot> {
+        let args = StackTraceArguments {
+            thread_id: thread.id,
+            levels: self.max_frames,
+            start_frame: None,
+            format: None,
+        };
+        let mut frames = self.call(&client, args).await?.stack_frames;
+        if let Some(max) = self.max_frames {
+            frames.truncate(max.max(0) as usize);
+        }
+        let frames = try_join_all(
+            frames
+                .into_iter()
+                .map(|frame| self.clone().frame(client.clone(), frame)),
+        )
+        .await?;
+        Ok(ThreadSnapshot { thread, frames })
+    }
+
+    async fn frame(self, client: Client, mut frame: StackFrame) -> DapResult<FrameSnapshot> {
+        let scopes = self
+            .call(&client, ScopesArguments { frame_id: frame.id })
+            .await?
+            .scopes;
+        let strip = self.strip_references;
+        let scopes = try_join_all(scopes.into_iter().map(|mut scope| {
+            let skip = self.max_depth == 0 || (scope.expensive && !self.expensive);
+            let variables = (!skip).then(|| {
+                self.clone()
+                    .variables(client.clone(), scope.variables_reference, 1)
+            });
+            async move {
+                let variables = match variables {
+                    Some(variables) => variables.await?,
+                    None => vec![],
+                };
+                if strip {
+                    scope.variables_reference = 0;
+                }
+                Ok::<_, DapError>(ScopeSnapshot { scope, variables })
+            }
+        }))
+        .await?;
+        if strip {
+            frame.id = 0;
+        }
+        Ok(FrameSnapshot { frame, scopes })
+    }
+
+    /// children of `reference`, `depth` is level of the children
+    fn variables(
+        self,
+        client: Client,
+        reference: i64,
+        depth: usize,
+    ) -> BoxFuture<DapResult<Vec<VariableSnapshot>>> {
+        Box::pin(async move {
+            if reference <= 0 {
+                return Ok(vec![]);
+            }
+            let args = VariablesArguments {
+                variables_reference: reference,
+                count: self.max_variables,
+                start: self.max_variables.map(|_| 0),
+                filter: None,
+                format: None,
+            };
+            let mut variables = self.call(&client, args).await?.variables;
+            if let Some(max) = self.max_variables {
+                variables.truncate(max.max(0) as usize);
+            }
+            let strip = self.strip_references;
+            try_join_all(variables.into_iter().map(|mut variable| {
+                let children = (depth < self.max_depth).then(|| {
+                    self.clone()
+                        .variables(client.clone(), variable.variables_reference, depth + 1)
+                });
+                async move {
+                    let children = match children {
+                        Some(children) => children.await?,
+                        None => vec![],
+                    };
+                    if strip {
+                        variable.variables_reference = 0;
+                    }
+                    Ok(VariableSnapshot { variable, children })
+                }
+            }))
+            .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use dap_ty::{Request, Response};
+    use serde_json::json;
+
+    use super::*;
+    use crate::server::tests::{connect, initialize};
+    use crate::server::{Ctx, Handler, Policy};
+
+    /// requests currently handled and most seen at once
+    #[derive(Default)]
+    struct InFlight {
+        now: AtomicUsize,
+        max: AtomicUsize,
+    }
+
+    /// adapter with threads 1 and 2, one frame each, a cheap scope and an expensive one,
+    /// every variable `n` having children `n * 10 + 1` and `n * 10 + 2`
+    fn adapter(in_flight: Arc<InFlight>) -> impl Handler {
+        move |_ctx: Ctx, req: Request| {
+            let in_flight = in_flight.clone();
+            async move {
+                let now = in_flight.now.fetch_add(1, Ordering::SeqCst) + 1;
+                in_flight.max.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                let args = req.arguments.clone().unwrap_or_default();
+                let body = match req.command.as_str() {
+                    "threads" => json!({"threads": [
+                        {"id": 1, "name": "main"},
+                        {"id": 2, "name": "worker"},
+                    ]}),
+                    "stackTrace" => {
+                        let thread = args["threadId"].as_i64().unwrap();
+                        json!({"stackFrames": [
+                            {"id": thread * 100, "name": "f", "line": 1, "column": 1},
+                        ]})
+                    }
+                    "scopes" => json!({"scopes": [
+                        {"name": "Locals", "variablesReference": 1, "expensive": false},
+                        {"name": "Globals", "variablesReference": 2, "expensive": true},
+                    ]}),
+                    "variables" => {
+                        let parent = args["variablesReference"].as_i64().unwrap();
+                        let variables: Vec<_> = (1..=2)
+                            .map(|i| {
+                                let reference = parent * 10 + i;
+                                json!({
+                                    "name": format!("v{}", reference),
+                                    "value": "{...}",
+                                    "variablesReference": reference,
+                                })
+                            })
+                            .collect();
+                        json!({ "variables": variables })
+                    }
+                    _ => json!({}),
+                };
+                in_flight.now.fetch_sub(1, Ordering::SeqCst);
+                Response::ok_with(req.seq, &req.command, body)
+            }
+        }
+    }
+
+    async fn take(snapshot: StoppedSnapshot) -> (Snapshot, Arc<InFlight>) {
+        let in_flight = Arc::new(InFlight::default());
+        let (client, _server) = connect(adapter(in_flight.clone()), Policy::default());
+        initialize(&client).await;
+        in_flight.max.store(0, Ordering::SeqCst);
+        (snapshot.take(&client).await.unwrap(), in_flight)
+    }
+
+    /// levels of variables below each scope
+    fn depth(variables: &[VariableSnapshot]) -> usize {
+        variables
+            .iter()
+            .map(|v| 1 + depth(&v.children))
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn depth_limit() {
+        for max_depth in 0..4 {
+            let (snapshot, _) = take(StoppedSnapshot::new().max_depth(max_depth)).await;
+            assert_eq!(snapshot.threads.len(), 2);
+            for thread in &snapshot.threads {
+                let scopes = &thread.frames[0].scopes;
+                assert_eq!(depth(&scopes[0].variables), max_depth);
+                assert!(scopes[1].variables.is_empty(), "expensive scope is skipped");
+            }
+        }
+
+        let (snapshot, _) = take(StoppedSnapshot::new().max_depth(1).expensive(true)).await;
+        let globals = &snapshot.threads[0].frames[0].scopes[1];
+        let names: Vec<_> = globals.variables.iter().map(|v| &v.variable.name).collect();
+        assert_eq!(names, ["v21", "v22"]);
+        // children below the limit are not fetched, their references are kept
+        assert!(globals.variables[0].children.is_empty());
+        assert_eq!(globals.variables[0].variable.variables_reference, 21);
+    }
+
+    #[tokio::test]
+    async fn strip_references() {
+        let (kept, _) = take(StoppedSnapshot::new()).await;
+        let frame = &kept.threads[1].frames[0];
+        assert_eq!(frame.frame.id, 200);
+        assert_eq!(frame.scopes[0].scope.variables_reference, 1);
+        assert_eq!(
+            frame.scopes[0].variables[1].children[0]
+                .variable
+                .variables_reference,
+            121
+        );
+
+        let (stripped, _) = take(StoppedSnapshot::new().strip_references(true)).await;
+        assert_eq!(stripped.threads.len(), kept.threads.len());
+        for thread in &stripped.threads {
+            for frame in &thread.frames {
+                assert_eq!(frame.frame.id, 0);
+                for scope in &frame.scopes {
+                    assert_eq!(scope.scope.variables_reference, 0);
+                    let mut variables: Vec<_> = scope.variables.iter().collect();
+                    while let Some(v) = variables.pop() {
+                        assert_eq!(v.variable.variables_reference, 0);
+                        variables.extend(&v.children);
+                    }
+                }
+            }
+        }
+        // only references differ
+        let names = |s: &Snapshot| serde_json::to_string(s).unwrap().matches("\"v").count();
+        assert_eq!(names(&stripped), names(&kept));
+    }
+
+    #[tokio::test]
+    async fn requests_in_flight_are_bounded() {
+        let (_, in_flight) = take(StoppedSnapshot::new().max_depth(3).max_concurrency(3)).await;
+        let max = in_flight.max.load(Ordering::SeqCst);
+        assert!((2..=3).contains(&max), "{} requests in flight", max);
+
+        let (_, in_flight) = take(StoppedSnapshot::new().max_depth(3).max_concurrency(1)).await;
+        assert_eq!(in_flight.max.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,215 @@
+//! protocol trace recorder
+//!
+//! every message a codec reads or writes can be appended to a [JSON Lines](https://jsonlines.org)
+//! file, one object per line:
+//!
+//! ```json
+//! {"ts":1666000000123456,"dir":"in","raw":"{\"seq\":1,\"type\":\"request\",\"command\":\"initialize\",...}"}
+//! ```
+//!
+//! - `ts`: microseconds since unix epoch when the message was read or written
+//! - `dir`: `"in"` for messages received from peer, `"out"` for messages sent to peer,
+//!   always relative to the process which records the trace
+//! - `raw`: message body exactly as on the wire, without `Content-Length` header,
+//!   invalid utf-8 sequences are replaced with `U+FFFD`
+//!
+//! set `DAP_TRACE=/path/to/trace.jsonl` to enable recording for every codec created
+//! afterwards, or pass a [`Tracer`] to codec's `with_tracer`.
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// environment variable holding trace file path
+pub const TRACE_ENV: &str = "DAP_TRACE";
+
+/// message direction, relative to recording process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Direction {
+    #[serde(rename = "in")]
+    Inbound,
+    #[serde(rename = "out")]
+    Outbound,
+}
+
+/// one line of trace file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TraceRecord {
+    /// microseconds since unix epoch
+    pub ts: u128,
+    pub dir: Direction,
+    /// raw message body
+    pub raw: String,
+}
+
+impl TraceRecord {
+    pub fn new(dir: Direction, raw: &[u8]) -> Self {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros())
+            .unwrap_or_default();
+        Self {
+            ts,
+            dir,
+            raw: String::from_utf8_lossy(raw).into_owned(),
+        }
+    }
+}
+
+/// append-only trace file writer
+///
+/// cheap to clone, clones write to the same file, so both halves of a connection
+/// can share one trace
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    file: Arc<Mutex<File>>,
+}
+
+impl Tracer {
+    /// open trace file in append mode, create it if not exists
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// create tracer from `DAP_TRACE` environment variable
+    ///
+    /// return `None` if variable is not set or file can not be opened
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os(TRACE_ENV)?;
+        match Self::create(&path) {
+            Ok(tracer) => Some(tracer),
+            Err(e) => {
+                tracing::error!("failed to open trace file {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// append a record, errors are logged and ignored, tracing should never break a session
+    pub fn record(&self, dir: Direction, raw: &[u8]) {
+        let record = TraceRecord::new(dir, raw);
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::error!("failed to serialize trace record: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(e) = file.write_all(&line) {
+            tracing::error!("failed to write trace record: {}", e);
+        }
+    }
+}
+
+/// read all records from a trace file
+pub fn read_trace<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<TraceRecord>> {
+    let content = std::fs::read_to_string(path)?;
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_trace(name: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("dap-trace-{}-{}.jsonl", name, std::process::id()));
+        std::fs::remove_file(&path).ok();
+        path
+    }
+
+    #[test]
+    fn record_and_read_back() {
+        let path = temp_trace("roundtrip");
+        let tracer = Tracer::create(&path).unwrap();
+        tracer.record(Direction::Inbound, br#"{"seq":1}"#);
+        tracer.clone().record(Direction::Outbound, b"not json \xff");
+        let records = read_trace(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].dir, Direction::Inbound);
+        assert_eq!(records[0].raw, r#"{"seq":1}"#);
+        assert_eq!(records[1].dir, Direction::Outbound);
+        assert_eq!(records[1].raw, "not json \u{fffd}");
+        assert!(records[0].ts <= records[1].ts);
+    }
+
+    #[test]
+    fn create_appends() {
+        let path = temp_trace("append");
+        Tracer::create(&path)
+            .unwrap()
+            .record(Direction::Inbound, b"1");
+        Tracer::create(&path)
+            .unwrap()
+            .record(Direction::Inbound, b"2");
+        let raw: Vec<_> = read_trace(&path)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.raw)
+            .collect();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(raw, ["1", "2"]);
+    }
+
+    #[test]
+    fn invalid_line_is_an_error() {
+        let path = temp_trace("invalid");
+        std::fs::write(
+            &path,
+            "{\"ts\":1,\"dir\":\"in\",\"raw\":\"\"}\n\nnot a record\n",
+        )
+        .unwrap();
+        let err = read_trace(&path).unwrap_err();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn codec_records_both_directions() {
+        use crate::Codec;
+
+        let path = temp_trace("codec");
+        let tracer = Tracer::create(&path).unwrap();
+        let body = r#"{"seq":1,"type":"event","event":"initialized"}"#;
+        let input = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let mut reader = Codec::new(input.as_bytes()).with_tracer(tracer.clone());
+        let msg = reader.receive().unwrap().unwrap();
+        let mut writer = Codec::new(Vec::new()).with_tracer(tracer);
+        writer.send(msg).unwrap();
+        let records = read_trace(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].dir, Direction::Inbound);
+        assert_eq!(records[0].raw, body);
+        assert_eq!(records[1].dir, Direction::Outbound);
+        let sent: serde_json::Value = serde_json::from_str(&records[1].raw).unwrap();
+        assert_eq!(
+            sent,
+            serde_json::from_str::<serde_json::Value>(body).unwrap()
+        );
+    }
+}
@@ -1,46 +1,106 @@
 use bytes::Buf;
 use bytes::BytesMut;
-use dap_ty::{Event, Request, Response, OneOf3};
+use dap_ty::{Event, OneOf3, Request, Response};
 
 use super::BUF_SIZE;
+use crate::error::{DapError, DapResult};
+use crate::trace::{Direction, Tracer};
 
-fn parse_header(headers: &str) -> Result<usize, String> {
-    let mut segs = headers.split(':');
-    let key = segs.next().unwrap();
-    let mut content_length = 0;
-    if key == "Content-Length" {
-        content_length = segs.next().unwrap().trim().parse().unwrap();
-    } else {
-        tracing::error!("unknown header {}", key);
-    }
-    if content_length == 0 {
-        let msg = "empty content length or missing Content-Length header";
-        tracing::error!(msg);
-        return Err(msg.to_string());
-    }
-    Ok(content_length)
+/// default limit of a single message body
+pub const MAX_CONTENT_LENGTH: usize = 64 * 1024 * 1024;
+
+fn parse_header(headers: &str, max: usize) -> DapResult<usize> {
+    let mut content_length = None;
+    for line in headers.split("\r\n") {
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| DapError::Header(format!("malformed header line {:?}", line)))?;
+        if key.trim().eq_ignore_ascii_case("Content-Length") {
+            let len = value
+                .trim()
+                .parse()
+                .map_err(|_| DapError::Header(format!("invalid Content-Length {:?}", value)))?;
+            content_length = Some(len);
+        } else {
+            tracing::warn!("unknown header {}", key);
+        }
+    }
+    match content_length {
+        Some(0) | None => Err(DapError::Header(
+            "empty content length or missing Content-Length header".to_string(),
+        )),
+        Some(len) if len > max => Err(DapError::FrameTooLarge { len, max }),
+        Some(len) => Ok(len),
+    }
+}
+
+/// a message as it is on the wire, for forwarding without re-encoding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// header lines including the empty line ending them
+    pub header: Vec<u8>,
+    pub body: Vec<u8>,
+}
+
+impl Frame {
+    /// frame `body` with a `Content-Length` header
+    pub fn new(body: Vec<u8>) -> Self {
+        Self {
+            header: format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes(),
+            body,
+        }
+    }
+
+    /// parse body as protocol message
+    pub fn message(&self) -> DapResult<OneOf3<Request, Response, Event>> {
+        serde_json::from_slice(&self.body).map_err(|source| DapError::Json {
+            raw: String::from_utf8_lossy(&self.body).into_owned(),
+            source,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct CodecState {
     pub read_content_length: usize,
+    /// raw header of message being read
+    pub read_header: Vec<u8>,
     pub read_buf: [u8; BUF_SIZE],
     pub read_data: BytesMut,
+    pub max_content_length: usize,
+    pub tracer: Option<Tracer>,
 }
 
 impl CodecState {
-    pub fn consume_body(&mut self) -> serde_json::Result<OneOf3<Request, Response, Event>> {
-        let msg = serde_json::from_slice(&self.read_data[..self.read_content_length])?;
+    pub fn consume_body(&mut self) -> DapResult<OneOf3<Request, Response, Event>> {
+        let body = &self.read_data[..self.read_content_length];
+        self.trace(Direction::Inbound, body);
+        let msg = serde_json::from_slice(body).map_err(|source| DapError::Json {
+            raw: String::from_utf8_lossy(body).into_owned(),
+            source,
+        });
         // reset state after read
         self.read_data.advance(self.read_content_length);
         self.read_content_length = 0;
-        Ok(msg)
+        msg
     }
 
-    pub fn parse_header(&mut self, headers: String) -> Result<(), String> {
-        let content_length = parse_header(&headers)?;
-        self.read_content_length = content_length;
-        Ok(())
+    /// take message being read without parsing it
+    pub fn consume_frame(&mut self) -> Frame {
+        let body = self.read_data.split_to(self.read_content_length).to_vec();
+        self.trace(Direction::Inbound, &body);
+        self.read_content_length = 0;
+        Frame {
+            header: std::mem::take(&mut self.read_header),
+            body,
+        }
+    }
+
+    /// record raw message body if tracing is enabled
+    pub fn trace(&self, dir: Direction, raw: &[u8]) {
+        if let Some(tracer) = &self.tracer {
+            tracer.record(dir, raw);
+        }
     }
 
     fn header_pos(&self) -> Option<usize> {
@@ -49,25 +109,112 @@ impl CodecState {
             .position(|s| s == [b'\r', b'\n', b'\r', b'\n'])
     }
 
-    pub fn try_parse_header(&mut self) -> Option<Result<(), String>> {
+    pub fn try_parse_header(&mut self) -> Option<DapResult<()>> {
         self.header_pos().map(|stop_at| {
-            let headers = String::from_utf8(self.read_data[..stop_at].to_vec()).unwrap();
-            self.read_data.advance(stop_at + 4);
-            self.parse_header(headers)
+            let headers = String::from_utf8_lossy(&self.read_data[..stop_at]).into_owned();
+            self.read_header = self.read_data.split_to(stop_at + 4).to_vec();
+            self.read_content_length = parse_header(&headers, self.max_content_length)?;
+            Ok(())
         })
     }
 
     pub fn body_ready(&self) -> bool {
         self.read_content_length <= self.read_data.len()
     }
+
+    /// append `count` bytes of `read_buf` to pending data, `count == 0` means eof
+    ///
+    /// eof between messages is a clean close and returns `Ok(false)`,
+    /// eof in the middle of a message is [`DapError::Closed`]
+    pub fn fill(&mut self, count: usize) -> DapResult<bool> {
+        if count == 0 {
+            return if self.read_data.is_empty() && self.read_content_length == 0 {
+                Ok(false)
+            } else {
+                Err(DapError::Closed)
+            };
+        }
+        self.read_data.extend_from_slice(&self.read_buf[..count]);
+        Ok(true)
+    }
 }
 
 impl Default for CodecState {
     fn default() -> Self {
         Self {
             read_content_length: Default::default(),
+            read_header: Vec::new(),
             read_buf: [0; BUF_SIZE],
             read_data: BytesMut::with_capacity(BUF_SIZE),
+            max_content_length: MAX_CONTENT_LENGTH,
+            tracer: Tracer::from_env(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_err(headers: &str) -> String {
+        match parse_header(headers, MAX_CONTENT_LENGTH) {
+            Err(DapError::Header(msg)) => msg,
+            other => panic!("expected header error, got {:?}", other),
         }
     }
+
+    #[test]
+    fn parse_content_length() {
+        assert_eq!(parse_header("Content-Length: 12", 100).unwrap(), 12);
+        assert_eq!(parse_header("content-length:12 ", 100).unwrap(), 12);
+        assert_eq!(
+            parse_header("Content-Type: application/json\r\nContent-Length: 3", 100).unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    fn malformed_headers() {
+        assert!(header_err("Content-Length 12").contains("malformed header line"));
+        assert!(header_err("Content-Length: twelve").contains("invalid Content-Length"));
+        assert!(header_err("Content-Length: -1").contains("invalid Content-Length"));
+        assert!(header_err("Content-Length: 0").contains("missing Content-Length"));
+        assert!(header_err("Content-Type: application/json").contains("missing Content-Length"));
+    }
+
+    #[test]
+    fn frame_too_large() {
+        assert!(matches!(
+            parse_header("Content-Length: 101", 100),
+            Err(DapError::FrameTooLarge { len: 101, max: 100 })
+        ));
+        assert_eq!(parse_header("Content-Length: 100", 100).unwrap(), 100);
+    }
+
+    #[test]
+    fn eof_between_and_inside_messages() {
+        let mut state = CodecState::default();
+        assert!(!state.fill(0).unwrap());
+
+        state.read_buf[..3].copy_from_slice(b"Con");
+        assert!(state.fill(3).unwrap());
+        assert!(matches!(state.fill(0), Err(DapError::Closed)));
+    }
+
+    #[test]
+    fn frame_keeps_header_and_body() {
+        let mut state = CodecState::default();
+        let raw = b"Content-Length: 2\r\nX-Extra: 1\r\n\r\n{}";
+        state.read_data.extend_from_slice(raw);
+        state.try_parse_header().unwrap().unwrap();
+        assert!(state.body_ready());
+        let frame = state.consume_frame();
+        assert_eq!(frame.header, b"Content-Length: 2\r\nX-Extra: 1\r\n\r\n");
+        assert_eq!(frame.body, b"{}");
+        assert!(matches!(frame.message(), Err(DapError::Json { raw, .. }) if raw == "{}"));
+        assert_eq!(
+            Frame::new(b"{}".to_vec()).header,
+            b"Content-Length: 2\r\n\r\n"
+        );
+    }
 }
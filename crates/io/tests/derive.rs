@@ -0,0 +1,128 @@
+//! requests and events derived with dap-derive behave like the ones of dap-ty
+#![cfg(feature = "derive")]
+
+use dap_ty::{impl_evt, impl_req, DapEvent, DapRequest, Event, FromEvent, FromReq, OneOf, Request};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, DapRequest)]
+#[dap(command = "myCustom", response = MyCustomResponseBody)]
+#[serde(rename_all = "camelCase")]
+struct MyCustomArguments {
+    thread_id: i64,
+    verbose: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct MyCustomResponseBody {
+    lines: Vec<String>,
+}
+
+/// same request implemented by `impl_req!`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManualArguments {
+    thread_id: i64,
+    verbose: bool,
+}
+
+impl_req!(ManualArguments, "myCustom", MyCustomResponseBody);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, DapRequest)]
+#[dap(command = "untyped")]
+struct UntypedArguments {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, DapEvent)]
+#[dap(event = "myEvent")]
+struct MyEventBody {
+    message: String,
+}
+
+/// same event implemented by `impl_evt!`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ManualEventBody {
+    message: String,
+}
+
+impl_evt!(ManualEventBody, "myEvent");
+
+fn parse_req<T: FromReq>(req: Request) -> (i64, T) {
+    match T::from_req(req) {
+        OneOf::This(parsed) => parsed.unwrap(),
+        OneOf::Other(req) => panic!("{} not parsed as {}", req.command, T::COMMAND),
+    }
+}
+
+fn parse_event<T: FromEvent>(event: Event) -> (i64, T) {
+    match T::from_event(event) {
+        OneOf::This(parsed) => parsed.unwrap(),
+        OneOf::Other(event) => panic!("{} not parsed as {}", event.event, T::EVENT),
+    }
+}
+
+#[test]
+fn derived_request_round_trip() {
+    let args = MyCustomArguments {
+        thread_id: 3,
+        verbose: true,
+    };
+    let req = args.clone().into_req(5);
+    assert_eq!(req.command, "myCustom");
+    assert_eq!(req.arguments, Some(json!({"threadId": 3, "verbose": true})));
+    assert_eq!(parse_req::<MyCustomArguments>(req.clone()), (5, args));
+
+    // interchangeable with impl_req!
+    let manual = ManualArguments {
+        thread_id: 3,
+        verbose: true,
+    };
+    assert_eq!(manual.clone().into_req(5), req);
+    assert_eq!(parse_req::<ManualArguments>(req), (5, manual));
+
+    let body = MyCustomArguments::ret(MyCustomResponseBody { lines: vec![] });
+    assert!(body.lines.is_empty());
+    let untyped: <UntypedArguments as FromReq>::Ret = json!({"any": 1});
+    assert_eq!(untyped["any"], 1);
+}
+
+#[test]
+fn derived_request_rejects_other_commands() {
+    let req = UntypedArguments {}.into_req(1);
+    assert!(matches!(
+        MyCustomArguments::from_req(req.clone()),
+        OneOf::Other(r) if r == req
+    ));
+    let mut invalid = MyCustomArguments {
+        thread_id: 1,
+        verbose: false,
+    }
+    .into_req(2);
+    invalid.arguments = Some(json!({"threadId": "one"}));
+    assert!(matches!(
+        MyCustomArguments::from_req(invalid),
+        OneOf::This(Err(_))
+    ));
+}
+
+#[test]
+fn derived_event_round_trip() {
+    let body = MyEventBody {
+        message: "hello".to_string(),
+    };
+    let event = body.clone().into_event(9);
+    assert_eq!(event.event, "myEvent");
+    assert_eq!(event.type_, "event");
+    assert_eq!(event.body, Some(json!({"message": "hello"})));
+    assert_eq!(parse_event::<MyEventBody>(event.clone()), (9, body));
+
+    // interchangeable with impl_evt!
+    let manual = ManualEventBody {
+        message: "hello".to_string(),
+    };
+    assert_eq!(manual.clone().into_event(9), event);
+    assert_eq!(parse_event::<ManualEventBody>(event.clone()), (9, manual));
+
+    let mut other = event;
+    other.event = "output".to_string();
+    assert!(matches!(MyEventBody::from_event(other), OneOf::Other(_)));
+}
@@ -0,0 +1,151 @@
+//! routes written with `#[dap_handler]` served by a `Router`
+#![cfg(feature = "derive")]
+
+use dap_io::{dap_handler, serve, Client, Ctx, Router};
+use dap_ty::{
+    Capabilities, DapRequest, EvaluateArguments, EvaluateResponseBody, FromReq,
+    InitializeRequestArguments, Request, Response, ScopesArguments, ScopesResponseBody,
+    ThreadsRequestArguments, ThreadsResponseBody,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::task::JoinHandle;
+
+/// answer `initialize`
+#[dap_handler]
+async fn on_initialize(
+    _ctx: &Ctx,
+    _args: InitializeRequestArguments,
+) -> Result<Capabilities, String> {
+    Ok(Capabilities::default())
+}
+
+/// echo expression, context taken by reference
+#[dap_handler]
+async fn on_evaluate(_ctx: &Ctx, args: EvaluateArguments) -> Result<EvaluateResponseBody, String> {
+    Ok(serde_json::from_value(json!({
+        "result": args.expression,
+        "variablesReference": 0,
+    }))
+    .unwrap())
+}
+
+/// context taken by value
+#[dap_handler]
+async fn on_threads(
+    _ctx: Ctx,
+    _args: ThreadsRequestArguments,
+) -> Result<ThreadsResponseBody, std::fmt::Error> {
+    Ok(ThreadsResponseBody::default())
+}
+
+#[dap_handler]
+async fn on_scopes(_ctx: &Ctx, args: ScopesArguments) -> Result<ScopesResponseBody, String> {
+    Err(format!("unknown frame {}", args.frame_id))
+}
+
+/// command without a route
+#[derive(Debug, Serialize, Deserialize, DapRequest)]
+#[dap(command = "modules")]
+struct Modules {}
+
+/// `evaluate` with an argument of wrong type
+#[derive(Debug, Serialize, Deserialize, DapRequest)]
+#[dap(command = "evaluate")]
+struct BadEvaluate {
+    expression: i64,
+}
+
+fn connect(router: Router) -> (Client, JoinHandle<dap_io::DapResult<()>>) {
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let (reader, writer) = tokio::io::split(server_io);
+    let server = tokio::spawn(serve(reader, writer, router));
+    let (reader, writer) = tokio::io::split(client_io);
+    (Client::spawn(reader, writer), server)
+}
+
+async fn initialize(client: &Client) {
+    let args: InitializeRequestArguments =
+        serde_json::from_value(json!({"adapterID": "test"})).unwrap();
+    client.call(args).await.unwrap();
+}
+
+fn router() -> Router {
+    Router::new()
+        .handler(on_initialize)
+        .handler(on_evaluate)
+        .handler(on_threads)
+        .handler(on_scopes)
+}
+
+#[tokio::test]
+async fn dispatch_by_command() {
+    let router = router();
+    let mut commands: Vec<_> = router.commands().collect();
+    commands.sort_unstable();
+    assert_eq!(commands, ["evaluate", "initialize", "scopes", "threads"]);
+
+    let (client, server) = connect(router);
+    initialize(&client).await;
+    let args: EvaluateArguments = serde_json::from_value(json!({"expression": "1 + 1"})).unwrap();
+    assert_eq!(client.call(args).await.unwrap().result, "1 + 1");
+    let threads = client
+        .call(ThreadsRequestArguments::default())
+        .await
+        .unwrap();
+    assert!(threads.threads.is_empty());
+
+    let resp = client
+        .request(ScopesArguments { frame_id: 3 })
+        .await
+        .unwrap();
+    assert!(!resp.success);
+    assert_eq!(resp.message.as_deref(), Some("unknown frame 3"));
+    drop(client);
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn invalid_arguments_are_an_error_response() {
+    let (client, server) = connect(router());
+    initialize(&client).await;
+    let resp = client.request(BadEvaluate { expression: 1 }).await.unwrap();
+    assert!(!resp.success);
+    assert_eq!(resp.command, EvaluateArguments::COMMAND);
+    let message = resp.message.unwrap();
+    assert!(
+        message.starts_with("invalid evaluate arguments"),
+        "{}",
+        message
+    );
+    drop(client);
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn unrouted_requests_go_to_fallback() {
+    let (client, server) = connect(router());
+    initialize(&client).await;
+    let resp = client.request(Modules {}).await.unwrap();
+    assert!(!resp.success);
+    assert_eq!(resp.message.as_deref(), Some("modules is not supported"));
+    drop(client);
+    server.await.unwrap().unwrap();
+
+    let router = router().fallback(|_ctx: Ctx, req: Request| async move {
+        Response::ok_with(req.seq, &req.command, json!({"fallback": req.command}))
+    });
+    let (client, server) = connect(router);
+    initialize(&client).await;
+    let resp = client.request(Modules {}).await.unwrap();
+    assert!(resp.success);
+    assert_eq!(resp.body, Some(json!({"fallback": "modules"})));
+    drop(client);
+    server.await.unwrap().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "duplicated route of evaluate")]
+fn duplicated_route_panics() {
+    let _ = Router::new().handler(on_evaluate).handler(on_evaluate);
+}
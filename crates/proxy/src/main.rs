@@ -0,0 +1,203 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    sync::mpsc,
+    thread,
+};
+
+use clap::Parser;
+use dap_io::{Codec, Frame, PathMapper, PathMapping, Tracer};
+use dap_ty::{Event, OneOf3, Request, Response};
+use serde_json::Value;
+
+type Message = OneOf3<Request, Response, Event>;
+type Reader = Codec<Box<dyn Read + Send>>;
+type Writer = Codec<Box<dyn Write + Send>>;
+
+/// forward debug adapter protocol traffic between an editor and an adapter,
+/// printing or recording every message
+#[derive(Debug, Clone, Parser)]
+struct Args {
+    /// accept editor connection on this addr instead of stdio, e.g. `127.0.0.1:4711`
+    #[clap(long)]
+    pub listen: Option<String>,
+    /// connect to adapter listening on this addr instead of spawning `adapter` command
+    #[clap(long)]
+    pub connect: Option<String>,
+    /// print every message to stderr
+    #[clap(long)]
+    pub pretty: bool,
+    /// record every message to a JSONL trace file, directions are relative to editor side
+    #[clap(long)]
+    pub record: Option<String>,
+    /// rewrite source paths, `local=remote`, local is editor side path, remote is
+    /// adapter side path, can be repeated
+    #[clap(long = "map")]
+    pub mappings: Vec<PathMapping>,
+    /// logging level
+    #[clap(long, short, default_value = "info")]
+    pub level: tracing::Level,
+    /// adapter command and its arguments, adapter talks over stdio
+    #[clap(last = true)]
+    pub adapter: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Flow {
+    EditorToAdapter,
+    AdapterToEditor,
+}
+
+fn print_message(flow: Flow, frame: &Frame) {
+    let arrow = match flow {
+        Flow::EditorToAdapter => "editor --> adapter",
+        Flow::AdapterToEditor => "editor <-- adapter",
+    };
+    let msg: Message = match frame.message() {
+        Ok(msg) => msg,
+        Err(e) => {
+            let body = String::from_utf8_lossy(&frame.body);
+            eprintln!("{} unknown message: {}\n{}", arrow, e, body);
+            return;
+        }
+    };
+    let summary = match &msg {
+        OneOf3::This(req) => format!("request {} #{}", req.command, req.seq),
+        OneOf3::Among(resp) => format!(
+            "response {} #{} {}",
+            resp.command,
+            resp.request_seq,
+            if resp.success { "ok" } else { "failed" }
+        ),
+        OneOf3::Other(event) => format!("event {} #{}", event.event, event.seq),
+    };
+    let body = serde_json::from_slice::<Value>(&frame.body)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_default();
+    eprintln!("{} {}\n{}", arrow, summary, body);
+}
+
+/// rewrite source paths of `frame`, return `None` if nothing changed
+fn map_frame(flow: Flow, frame: &Frame, mapper: &PathMapper) -> Option<Frame> {
+    let mut value: Value = serde_json::from_slice(&frame.body).ok()?;
+    let changed = match flow {
+        Flow::EditorToAdapter => mapper.map_value_to_adapter(&mut value),
+        Flow::AdapterToEditor => mapper.map_value_to_client(&mut value),
+    };
+    if !changed {
+        return None;
+    }
+    serde_json::to_vec(&value).ok().map(Frame::new)
+}
+
+/// forward messages until either side closes
+///
+/// frames are forwarded byte for byte, only frames whose paths are rewritten by
+/// `mapper` are encoded again
+fn forward(flow: Flow, mut reader: Reader, mut writer: Writer, mapper: PathMapper, pretty: bool) {
+    loop {
+        let frame = match reader.receive_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                tracing::info!("{:?} stopped: peer closed", flow);
+                return;
+            }
+            Err(e) => {
+                tracing::info!("{:?} stopped: {}", flow, e);
+                return;
+            }
+        };
+        if pretty {
+            print_message(flow, &frame);
+        }
+        let mapped = if mapper.is_empty() {
+            None
+        } else {
+            map_frame(flow, &frame, &mapper)
+        };
+        if let Err(e) = writer.send_frame(mapped.as_ref().unwrap_or(&frame)) {
+            tracing::info!("{:?} stopped: {}", flow, e);
+            return;
+        }
+    }
+}
+
+fn editor_side(args: &Args) -> std::io::Result<(Reader, Writer)> {
+    let (reader, writer): (Box<dyn Read + Send>, Box<dyn Write + Send>) = match &args.listen {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            tracing::info!("waiting editor on {}", addr);
+            let (stream, peer) = listener.accept()?;
+            tracing::info!("editor connected from {}", peer);
+            (Box::new(stream.try_clone()?), Box::new(stream))
+        }
+        None => (Box::new(std::io::stdin()), Box::new(std::io::stdout())),
+    };
+    let (mut reader, mut writer) = (Codec::new(reader), Codec::new(writer));
+    if let Some(path) = &args.record {
+        let tracer = Tracer::create(path)?;
+        reader = reader.with_tracer(tracer.clone());
+        writer = writer.with_tracer(tracer);
+    }
+    Ok((reader, writer))
+}
+
+fn adapter_side(args: &Args) -> std::io::Result<(Reader, Writer, Option<Child>)> {
+    if let Some(addr) = &args.connect {
+        let stream = TcpStream::connect(addr)?;
+        tracing::info!("connected to adapter {}", addr);
+        let reader: Box<dyn Read + Send> = Box::new(stream.try_clone()?);
+        let writer: Box<dyn Write + Send> = Box::new(stream);
+        return Ok((Codec::new(reader), Codec::new(writer), None));
+    }
+    let (cmd, cmd_args) = args.adapter.split_first().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "either --connect or adapter command is required",
+        )
+    })?;
+    let mut child = Command::new(cmd)
+        .args(cmd_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()?;
+    tracing::info!("spawned adapter {} pid {}", cmd, child.id());
+    let reader: Box<dyn Read + Send> = Box::new(child.stdout.take().unwrap());
+    let writer: Box<dyn Write + Send> = Box::new(child.stdin.take().unwrap());
+    Ok((Codec::new(reader), Codec::new(writer), Some(child)))
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    // stdout may be the editor channel, log to stderr only
+    tracing_subscriber::fmt()
+        .with_max_level(args.level)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let (adapter_reader, adapter_writer, mut child) = adapter_side(&args)?;
+    let (editor_reader, editor_writer) = editor_side(&args)?;
+
+    let (done_tx, done_rx) = mpsc::channel();
+    for (flow, reader, writer) in [
+        (Flow::EditorToAdapter, editor_reader, adapter_writer),
+        (Flow::AdapterToEditor, adapter_reader, editor_writer),
+    ] {
+        let done_tx = done_tx.clone();
+        let pretty = args.pretty;
+        let mapper = PathMapper::new(args.mappings.clone());
+        thread::spawn(move || {
+            forward(flow, reader, writer, mapper, pretty);
+            done_tx.send(()).ok();
+        });
+    }
+    // either side closing ends the session
+    done_rx.recv().ok();
+    if let Some(child) = child.as_mut() {
+        child.kill().ok();
+        child.wait().ok();
+    }
+    Ok(())
+}
@@ -1,7 +1,46 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 mod protocol;
 pub use protocol::*;
 
+#[cfg(feature = "derive")]
+pub use dap_derive::{DapEvent, DapRequest};
+
+/// used by code generated by dap-derive, not public API
+#[doc(hidden)]
+pub mod __private {
+    pub use serde_json;
+}
+
+/// check `type` of [`Request`], [`Response`] and [`Event`] while deserializing,
+/// otherwise untagged [`OneOf3`] takes every response for a request
+mod msg_type {
+    use serde::de::{Deserialize, Deserializer, Error, Unexpected};
+
+    fn expect<'de, D: Deserializer<'de>>(
+        de: D,
+        expected: &'static str,
+    ) -> Result<String, D::Error> {
+        let ty = String::deserialize(de)?;
+        if ty == expected {
+            Ok(ty)
+        } else {
+            Err(D::Error::invalid_value(Unexpected::Str(&ty), &expected))
+        }
+    }
+
+    pub fn request<'de, D: Deserializer<'de>>(de: D) -> Result<String, D::Error> {
+        expect(de, "request")
+    }
+
+    pub fn response<'de, D: Deserializer<'de>>(de: D) -> Result<String, D::Error> {
+        expect(de, "response")
+    }
+
+    pub fn event<'de, D: Deserializer<'de>>(de: D) -> Result<String, D::Error> {
+        expect(de, "event")
+    }
+}
+
 pub trait FromReq: Sized + Serialize {
     const COMMAND: &'static str;
     type Ret;
@@ -60,7 +99,46 @@ macro_rules! impl_req {
     };
 }
 
-impl_req!(AttachRequestArguments, "attach");
+/// like [`impl_req`], for arguments generic over implementation specific attributes
+macro_rules! impl_generic_req {
+    ($type:ident, $method:literal) => {
+        impl<T: Serialize + DeserializeOwned> FromReq for $type<T> {
+            const COMMAND: &'static str = $method;
+            type Ret = serde_json::Value;
+
+            fn from_req(req: Request) -> OneOf<Result<(i64, Self), serde_json::Error>, Request> {
+                if <Self as FromReq>::can_cast(&req) {
+                    let Request { seq, arguments, .. } = req;
+                    OneOf::This(
+                        serde_json::from_value(
+                            arguments.unwrap_or_else(|| serde_json::Value::Null),
+                        )
+                        .map(|params| (seq, params)),
+                    )
+                } else {
+                    OneOf::Other(req)
+                }
+            }
+        }
+
+        impl<T> $type<T> {
+            /// helper function for user do not need to remember
+            /// result type of a request
+            pub fn ret(result: serde_json::Value) -> serde_json::Value {
+                result
+            }
+
+            /// parse `__restart` data sent back by client, see
+            /// [`TerminatedEventBody::with_restart`]
+            pub fn restart_data<R: DeserializeOwned>(&self) -> Option<serde_json::Result<R>> {
+                self.restart.clone().map(serde_json::from_value)
+            }
+        }
+    };
+}
+
+impl_generic_req!(AttachRequestArguments, "attach");
+impl_req!(CancelArguments, "cancel");
 impl_req!(CompletionsArguments, "completions", CompletionsResponseBody);
 impl_req!(ConfigurationDoneArguments, "configurationDone");
 impl_req!(ContinueArguments, "continue", ContinueResponseBody);
@@ -79,7 +157,7 @@ impl_req!(
 impl_req!(GotoArguments, "goto");
 impl_req!(GotoTargetsArguments, "gotoTargets", GotoTargetsResponseBody);
 impl_req!(InitializeRequestArguments, "initialize", Capabilities);
-impl_req!(LaunchRequestArguments, "launch");
+impl_generic_req!(LaunchRequestArguments, "launch");
 impl_req!(
     LoadedSourcesArguments,
     "loadedSources",
@@ -91,6 +169,11 @@ impl_req!(PauseArguments, "pause");
 impl_req!(RestartArguments, "restart");
 impl_req!(RestartFrameArguments, "restartFrame");
 impl_req!(ReverseContinueArguments, "reverseContinue");
+impl_req!(
+    RunInTerminalRequestArguments,
+    "runInTerminal",
+    RunInTerminalResponseBody
+);
 impl_req!(ScopesArguments, "scopes", ScopesResponseBody);
 impl_req!(
     SetBreakpointsArguments,
@@ -113,6 +196,11 @@ impl_req!(
     "setFunctionBreakpoints",
     SetFunctionBreakpointsResponseBody
 );
+impl_req!(
+    SetInstructionBreakpointsArguments,
+    "setInstructionBreakpoints",
+    SetInstructionBreakpointsResponseBody
+);
 impl_req!(SetVariableArguments, "setVariable", SetVariableResponseBody);
 impl_req!(SourceArguments, "source", SourceResponseBody);
 impl_req!(StackTraceArguments, "stackTrace", StackTraceResponseBody);
@@ -180,15 +268,26 @@ impl_evt!(InitializedEventBody, "initialized");
 // impl_evt!(Invalid, "invalidated");
 impl_evt!(LoadedSourceEventBody, "loadedSource");
 // impl_evt!(MemoryEventBody, "memory");
-impl_req!(ModuleEventBody, "module");
-impl_req!(OutputEventBody, "output");
-impl_req!(ProcessEventBody, "process");
-// impl_req!(ProcessEndEventBody, "progressEnd");
-// impl_req!(ProcessStartEventBody, "progressStart");
-// impl_req!(ProcessUpdateEventBody, "progressUpdate");
-impl_req!(StoppedEventBody, "stopped");
-impl_req!(TerminatedEventBody, "terminated");
-impl_req!(ThreadEventBody, "thread");
+impl_evt!(ModuleEventBody, "module");
+impl_evt!(OutputEventBody, "output");
+impl_evt!(ProcessEventBody, "process");
+impl_evt!(ProgressEndEventBody, "progressEnd");
+impl_evt!(ProgressStartEventBody, "progressStart");
+impl_evt!(ProgressUpdateEventBody, "progressUpdate");
+impl_evt!(StoppedEventBody, "stopped");
+impl_evt!(TerminatedEventBody, "terminated");
+impl_evt!(ThreadEventBody, "thread");
+
+impl TerminatedEventBody {
+    /// ask client to restart session, `data` is sent back as `__restart` attribute of
+    /// next `launch` or `attach` request
+    pub fn with_restart<R: Serialize>(data: &R) -> serde_json::Result<Self> {
+        Ok(Self {
+            error: None,
+            restart: Some(serde_json::to_value(data)?),
+        })
+    }
+}
 
 impl Response {
     pub fn ok_with<T: Serialize, B: Into<Option<T>>>(seq: i64, command: &str, body: B) -> Response {
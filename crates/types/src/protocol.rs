@@ -107,13 +107,17 @@ pub struct AttachRequest {
 }
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 /// Arguments for 'attach' request. Additional attributes are implementation specific.
-pub struct AttachRequestArguments {
+pub struct AttachRequestArguments<T = serde_json::Map<String, serde_json::Value>> {
     /// Optional data from the previous, restarted session.
     /// The data is sent as the 'restart' attribute of the 'terminated' event.
     /// The client should leave the data intact.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "__restart")]
     pub restart: Option<serde_json::Value>,
+
+    /// Implementation specific attributes.
+    #[serde(flatten)]
+    pub extra: T,
 }
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct AttachResponse {
@@ -190,6 +194,33 @@ pub struct BreakpointEvent {
     #[serde(rename = "type")]
     pub type_: String,
 }
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+/// Arguments for 'cancel' request.
+pub struct CancelArguments {
+    /// The ID (attribute 'seq') of the request to cancel. If missing no request is cancelled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<i64>,
+    /// The ID (attribute 'progressId') of the progress to cancel. If missing no progress is
+    /// cancelled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CancelRequest {
+    /// Object containing arguments for the command.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<CancelArguments>,
+    /// The command to execute.
+    pub command: String,
+    /// Sequence number.
+    pub seq: i64,
+    /// Message type.
+    #[serde(rename = "type")]
+    pub type_: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
 /// Information about the capabilities of a debug adapter.
 pub struct Capabilities {
@@ -209,6 +240,10 @@ pub struct Capabilities {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "supportedChecksumAlgorithms")]
     pub supported_checksum_algorithms: Option<Vec<ChecksumAlgorithm>>,
+    /// The debug adapter supports the 'cancel' request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "supportsCancelRequest")]
+    pub supports_cancel_request: Option<bool>,
     /// The debug adapter supports the 'completions' request.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "supportsCompletionsRequest")]
@@ -730,7 +765,7 @@ pub struct DisassembleResponse {
     pub type_: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
 /// Arguments for 'disconnect' request.
 pub struct DisconnectArguments {
     /// A value of true indicates that this 'disconnect' request is part of a restart sequence.
@@ -895,7 +930,7 @@ pub struct Event {
     /// Sequence number.
     pub seq: i64,
     /// Message type.
-    #[serde(rename = "type")]
+    #[serde(rename = "type", deserialize_with = "crate::msg_type::event")]
     pub type_: String,
 }
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -1210,6 +1245,10 @@ pub struct InitializeRequestArguments {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "pathFormat")]
     pub path_format: Option<String>,
+    /// Client supports progress reporting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "supportsProgressReporting")]
+    pub supports_progress_reporting: Option<bool>,
     /// Client supports the runInTerminal request.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "supportsRunInTerminalRequest")]
@@ -1274,7 +1313,7 @@ pub struct LaunchRequest {
 }
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 /// Arguments for 'launch' request. Additional attributes are implementation specific.
-pub struct LaunchRequestArguments {
+pub struct LaunchRequestArguments<T = serde_json::Map<String, serde_json::Value>> {
     /// Optional data from the previous, restarted session.
     /// The data is sent as the 'restart' attribute of the 'terminated' event.
     /// The client should leave the data intact.
@@ -1286,8 +1325,9 @@ pub struct LaunchRequestArguments {
     #[serde(rename = "noDebug")]
     pub no_debug: Option<bool>,
 
+    /// Implementation specific attributes.
     #[serde(flatten)]
-    pub extra: serde_json::Map<String, serde_json::Value>,
+    pub extra: T,
 }
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct LaunchResponse {
@@ -1748,6 +1788,55 @@ pub struct ProcessEvent {
     #[serde(rename = "type")]
     pub type_: String,
 }
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+/// Body of 'progressStart' event, signals that a long running operation is about to start.
+pub struct ProgressStartEventBody {
+    /// An ID that must be used in subsequent 'progressUpdate' and 'progressEnd' events to make
+    /// them refer to the same progress reporting. IDs must be unique within a debug session.
+    pub progress_id: String,
+    /// Mandatory (short) title of the progress reporting. Shown in the UI to describe the long
+    /// running operation.
+    pub title: String,
+    /// The request ID that this progress report is related to. If specified a debug adapter is
+    /// expected to emit progress events for the long running request until the request has been
+    /// either completed or cancelled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<i64>,
+    /// If true, the request that reports progress may be canceled with a 'cancel' request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cancellable: Option<bool>,
+    /// Optional, more detailed progress message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Optional progress percentage to display (value range: 0 to 100).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentage: Option<f64>,
+}
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+/// Body of 'progressUpdate' event.
+pub struct ProgressUpdateEventBody {
+    /// The ID that was introduced in the initial 'progressStart' event.
+    pub progress_id: String,
+    /// Optional, more detailed progress message. If omitted, the previous message (if any) is
+    /// used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Optional progress percentage to display (value range: 0 to 100).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentage: Option<f64>,
+}
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+/// Body of 'progressEnd' event.
+pub struct ProgressEndEventBody {
+    /// The ID that was introduced in the initial 'progressStart' event.
+    pub progress_id: String,
+    /// Optional, more detailed progress message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 /// Base class of requests, responses, and events.
 pub struct ProtocolMessage {
@@ -1768,7 +1857,7 @@ pub struct Request {
     /// Sequence number.
     pub seq: i64,
     /// Message type.
-    #[serde(rename = "type")]
+    #[serde(rename = "type", deserialize_with = "crate::msg_type::request")]
     pub type_: String,
 }
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -1788,7 +1877,7 @@ pub struct Response {
     /// Outcome of the request.
     pub success: bool,
     /// Message type.
-    #[serde(rename = "type")]
+    #[serde(rename = "type", deserialize_with = "crate::msg_type::response")]
     pub type_: String,
 }
 
@@ -3113,7 +3202,7 @@ pub struct ThreadsRequest {
     pub type_: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
 pub struct ThreadsRequestArguments(serde_json::Value);
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]